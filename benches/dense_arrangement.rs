@@ -0,0 +1,57 @@
+// a hand-rolled benchmark (no external harness/dependency, matching how the
+// rest of this crate avoids pulling in a crate for something this small):
+// renders a dense arrangement through `play_source` and reports how many
+// times realtime it runs at. Run with `cargo bench`.
+//
+// This exercises the timing cache added for synth-95 (`InstructionTiming`,
+// which avoids recomputing an instruction's slot length and swing/humanize
+// delay every sample). The time it reports covers the whole render,
+// oscillator/envelope work included; that part isn't cached, since
+// vibrato, tremolo, portamento, pitch bend, and arpeggiation all modulate
+// it continuously against live `t` and have no single per-note value to
+// precompute the way the timing above does.
+
+use megalovania::{Adsr, Instruction, Source, Track, Waveform};
+
+const SAMPLE_RATE: u32 = 44100;
+const TRACK_COUNT: usize = 32;
+const NOTES_PER_TRACK: usize = 64;
+const RENDER_SECONDS: f64 = 10.0;
+
+fn dense_track() -> Track {
+	let instructions = (0 .. NOTES_PER_TRACK)
+		.map(|i| Instruction::Note {
+			pitch: (i % 12) as i32 - 6,
+			length: 1.0 / 16.0,
+			velocity: 0.8,
+			tied: false,
+			gate: 0.8,
+			probability: 1.0,
+			pan: None,
+		})
+		.collect();
+
+	Track::new(instructions, Waveform::Sawtooth, Adsr {attack: 0.01, decay: 0.05, sustain: 0.7, release: 0.05, curve: 1.0}, 0.0)
+}
+
+fn main() {
+	let tracks = (0 .. TRACK_COUNT).map(|_| dense_track()).collect();
+	let mut source = Source::new(tracks, 140.0, None);
+
+	let sample_count = (RENDER_SECONDS * f64::from(SAMPLE_RATE)) as usize;
+
+	let start = std::time::Instant::now();
+
+	let mut samples = source.samples(SAMPLE_RATE);
+	for _ in 0 .. sample_count {
+		samples.next();
+	}
+
+	let elapsed = start.elapsed();
+	let realtime_factor = RENDER_SECONDS / elapsed.as_secs_f64();
+
+	println!(
+		"rendered {:.1}s of audio across {} tracks in {:.3}s ({:.1}x realtime)",
+		RENDER_SECONDS, TRACK_COUNT, elapsed.as_secs_f64(), realtime_factor,
+	);
+}
@@ -0,0 +1,3670 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::Write;
+use std::sync::OnceLock;
+
+mod midi;
+#[cfg(feature = "serde-support")]
+mod song_spec;
+
+pub use midi::{export_midi, from_midi, MidiError};
+#[cfg(feature = "serde-support")]
+pub use song_spec::{from_json, from_toml, DrumTrackSpec, SongError, SourceSpec, TrackSpec};
+
+const TAU: f64 = 2.0 * PI;
+
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum Instruction {
+	// `tied` skips this note's attack/decay stage and jumps straight to the
+	// sustain level, so a tied run of same-pitch notes sounds like one
+	// continuous tone instead of re-articulating on every instruction.
+	// `gate` in 0.0..=1.0 is the fraction of `length` that's actually voiced
+	// before the note falls silent (1.0 legato, ~0.4 staccato); it's separate
+	// from the ADSR shape, which simply runs faster to fit inside the gate.
+	// `probability` in 0.0..=1.0 is the chance this note actually sounds on
+	// any given pass; below 1.0 it's decided fresh (via the track's RNG
+	// state) each time playback reaches it, so a looped section can vary
+	// from one repeat to the next. 1.0 (the default) always sounds, as before.
+	// `pan` overrides the track's own `pan` for just this note, in -1.0..=1.0;
+	// `None` (the default) leaves the track's pan untouched, letting a single
+	// voice dart across the stereo field note-to-note without splitting it
+	// into separate hard-panned tracks
+	Note {pitch: i32, length: f64, velocity: f64, tied: bool, gate: f64, probability: f64, pan: Option<f64>},
+	Chord {pitches: Vec<i32>, length: f64},
+	// glissando: the effective pitch slides linearly in log-frequency space
+	// from `from` to `to` over `length`
+	Slide {from: i32, to: i32, length: f64},
+	Rest {length: f64},
+	Tempo {bpm: f64},
+	// a very short note played just before the following instruction,
+	// stealing `GRACE_LENGTH` from its start rather than occupying a full
+	// slot of its own. See `play_track`'s handling of `grace_debt`
+	Grace {pitch: i32},
+	// starts a linear ramp of the track's overall dynamic level to
+	// `to_velocity` over `over` (in the same whole-note-fraction units as
+	// `length`), scaling every following `Note`'s own `velocity` by the
+	// ramp's current position rather than overriding it outright, so notes
+	// under the same crescendo keep their relative balance. Takes no slot of
+	// its own, like `Tempo`; the ramp is evaluated against absolute time
+	// rather than once per note, so it keeps sliding smoothly even if a
+	// note's own length straddles `over`'s end
+	Crescendo {to_velocity: f64, over: f64},
+}
+
+// the default velocity for a Note, used by song data that doesn't care about dynamics
+pub const FULL_VELOCITY: f64 = 1.0;
+
+// the fixed duration of a `Instruction::Grace`, as a fraction of a whole
+// note. Not exposed for tuning; a grace note is meant to read as an
+// ornament, not a notated rhythmic value
+pub(crate) const GRACE_LENGTH: f64 = N32ND;
+
+impl Instruction {
+	fn length(&self) -> f64 {
+		match self {
+			Instruction::Note {length, ..} => *length,
+			Instruction::Chord {length, ..} => *length,
+			Instruction::Slide {length, ..} => *length,
+			Instruction::Rest {length} => *length,
+			Instruction::Tempo {..} => 0.0,
+			// takes no nominal slot of its own; `play_track` gives it a real,
+			// fixed `GRACE_LENGTH` duration and borrows that time from the
+			// start of whichever instruction follows
+			Instruction::Grace {..} => 0.0,
+			// takes no slot of its own, like `Tempo`
+			Instruction::Crescendo {..} => 0.0,
+		}
+	}
+}
+
+// `Copy` so a live waveform switch (see `Source::set_waveform`) can be
+// broadcast to every track's `pending_waveform` without cloning ceremony
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum Waveform {
+	Sin,
+	Sawtooth,
+	// PolyBLEP-corrected sawtooth; aliases far less than `Sawtooth` on high notes
+	SawtoothAntiAliased,
+	Square,
+	Triangle,
+	// additive-synthesis square/triangle: summed from their odd harmonics up
+	// to Nyquist instead of read off a fixed-shape table, so they alias far
+	// less than `Square`/`Triangle` on high notes, the same trade `Sawtooth`
+	// makes for `SawtoothAntiAliased`
+	SquareAntiAliased,
+	TriangleAntiAliased,
+	// classic chiptune tone; `duty` in 0.0..=1.0 is the fraction of the
+	// period spent at +1 before dropping to -1. 0.5 is a plain square wave
+	Pulse {duty: f64},
+	// frequency modulation: a sine modulator running at `carrier * ratio`
+	// phase-modulates a sine carrier by `index` radians. `ratio: 1.0` with a
+	// small `index` is a mild vibrato-like tone; larger indices and
+	// non-integer ratios produce inharmonic, bell-like or metallic timbres
+	Fm {ratio: f64, index: f64},
+	// hard sync: a slave oscillator running at `frequency * slave_ratio`
+	// whose phase is forced back to zero every time the note's own
+	// (master) cycle wraps. The master itself is inaudible; only the
+	// slave, buzzing through however much of its cycle it gets to complete
+	// before the next reset, is heard. Sweeping `slave_ratio` is the
+	// classic hard-sync sweep
+	Sync {slave_ratio: f64},
+	// uniform white noise in -1.0..=1.0, useful for percussion when paired
+	// with a short envelope. Ignores pitch entirely
+	Noise,
+}
+
+// how pitch offsets are mapped to frequency ratios
+// `Copy` so a `Track`'s release tail (see `Track::with_release_tail`) can
+// snapshot the temperament in effect when a note started, without cloning
+// ceremony
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum Temperament {
+	// the standard 12-tone equal-tempered scale: each semitone is a fixed
+	// `2^(1/12)` ratio, so every key sounds identical
+	EqualTemperament,
+	// 5-limit just intonation: intervals relative to `tonic` use small
+	// integer ratios instead of `2^(1/12)`, giving purer thirds and fifths
+	// at the cost of other keys sounding progressively out of tune
+	JustIntonation {tonic: i32},
+}
+
+// 5-limit just intonation ratios for each semitone above the tonic, within
+// one octave
+const JUST_INTONATION_RATIOS: [f64; 12] = [
+	1.0 / 1.0,
+	16.0 / 15.0,
+	9.0 / 8.0,
+	6.0 / 5.0,
+	5.0 / 4.0,
+	4.0 / 3.0,
+	45.0 / 32.0,
+	3.0 / 2.0,
+	8.0 / 5.0,
+	5.0 / 3.0,
+	9.0 / 5.0,
+	15.0 / 8.0,
+];
+
+// `Copy` so a `Track`'s release tail can snapshot the envelope shape a note
+// started with, without cloning ceremony
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+pub struct Adsr {
+	pub attack: f64,
+	pub decay: f64,
+	pub sustain: f64,
+	pub release: f64,
+	// shapes the attack and release ramps: 1.0 is linear (the historical
+	// behavior), and values above 1.0 bow the ramp toward an exponential-ish
+	// curve, closer to how a real instrument's amplitude actually decays
+	pub curve: f64,
+}
+
+// low-frequency pitch modulation applied to a held note. The depth ramps in
+// linearly starting at `delay` seconds into the note and reaching full depth
+// `delay` seconds after that, so short notes barely shimmer while long held
+// ones do.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vibrato {
+	pub rate_hz: f64,
+	pub depth_semitones: f64,
+	pub delay: f64,
+}
+
+// amplitude modulation applied to a held note. `depth` of 0.0 is a no-op;
+// `depth` of 1.0 dips all the way to silence at the bottom of each cycle
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tremolo {
+	pub rate_hz: f64,
+	pub depth: f64,
+}
+
+// stacks `voices` detuned copies of a note's waveform for a thicker,
+// "supersaw"-style unison sound. The voices are spread symmetrically across
+// `detune_cents` and averaged back down so unison doesn't change a note's
+// overall loudness.
+//
+// `spread` is meant to place the voices across the stereo field, but the
+// mixing path only carries one signal per note today; it's accepted here so
+// callers can already commit to the field name, but is currently unused and
+// every voice sums into the same mono signal
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+pub struct Unison {
+	pub voices: u32,
+	pub detune_cents: f64,
+	pub spread: f64,
+}
+
+// bundles a waveform, envelope, and the handful of texture parameters that
+// usually travel together into a single named preset, so a track doesn't
+// have to restate `with_vibrato`/`with_tremolo`/`with_sub_level`/
+// `with_unison` by hand every time it wants a recognizable instrument voice.
+// Fields are public, so a custom instrument is just a struct literal; the
+// associated functions below are a few common presets
+pub struct Instrument {
+	pub waveform: Waveform,
+	pub adsr: Adsr,
+	pub vibrato: Option<Vibrato>,
+	pub tremolo: Option<Tremolo>,
+	pub sub_level: f64,
+	pub unison: Option<Unison>,
+}
+
+impl Instrument {
+	// a hollow, sustained tone with heavy sub weight and a slow amplitude
+	// wobble, like a drawbar organ held at a single stop
+	pub fn organ() -> Self {
+		Instrument {
+			waveform: Waveform::Square,
+			adsr: Adsr {attack: 0.01, decay: 0.0, sustain: 1.0, release: 0.05, curve: 1.0},
+			vibrato: None,
+			tremolo: Some(Tremolo {rate_hz: 5.0, depth: 0.15}),
+			sub_level: 0.3,
+			unison: None,
+		}
+	}
+
+	// a short, plucked tone: fast attack, no sustain, and a quick decay
+	pub fn pluck() -> Self {
+		Instrument {
+			waveform: Waveform::Triangle,
+			adsr: Adsr {attack: 0.001, decay: 0.2, sustain: 0.0, release: 0.05, curve: 2.0},
+			vibrato: None,
+			tremolo: None,
+			sub_level: 0.0,
+			unison: None,
+		}
+	}
+
+	// a slow-swelling, detuned tone meant to sit underneath a melody
+	pub fn pad() -> Self {
+		Instrument {
+			waveform: Waveform::SawtoothAntiAliased,
+			adsr: Adsr {attack: 0.8, decay: 0.2, sustain: 0.8, release: 1.2, curve: 1.5},
+			vibrato: Some(Vibrato {rate_hz: 4.5, depth_semitones: 0.1, delay: 0.5}),
+			tremolo: None,
+			sub_level: 0.0,
+			unison: Some(Unison {voices: 3, detune_cents: 12.0, spread: 1.0}),
+		}
+	}
+}
+
+// the order a chord's pitches are cycled through by `Track::with_arpeggio`
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum ArpPattern {
+	// lowest pitch to highest, then back to the lowest
+	Up,
+	// highest pitch to lowest, then back to the highest
+	Down,
+	// lowest to highest and back down again without repeating either end,
+	// e.g. a 4-note chord cycles 1-2-3-4-3-2
+	UpDown,
+}
+
+impl Default for Adsr {
+	fn default() -> Self {
+		Adsr {
+			attack: 0.1,
+			decay: 0.0,
+			sustain: 1.0,
+			release: 0.1,
+			curve: 1.0,
+		}
+	}
+}
+
+// a musical time signature: `numerator` beats per measure, each beat being a
+// `denominator`-th note. Note lengths are always fractions of a whole note
+// regardless of this, but measure and beat durations (used by the metronome)
+// depend on it. Swing's eighth-note grid does not: it is always an eighth of
+// a whole note at the current tempo, whatever the signature.
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeSignature {
+	pub numerator: u32,
+	pub denominator: u32,
+}
+
+impl Default for TimeSignature {
+	fn default() -> Self {
+		TimeSignature {numerator: 4, denominator: 4}
+	}
+}
+
+pub struct Track {
+	instructions: Vec<Instruction>,
+	waveform: Waveform,
+	adsr: Adsr,
+	pan: f64,
+	vibrato: Option<Vibrato>,
+	tremolo: Option<Tremolo>,
+	// 0.0 disables portamento; otherwise each new note glides from the pitch
+	// of the previous note over this many milliseconds
+	portamento_ms: f64,
+	// the pitch of the last note that finished playing on this track, used
+	// as the glide's starting point. None until the first note has played
+	previous_pitch: Option<i32>,
+	start_of_instruction: f64,
+	current_instruction: usize,
+	muted: bool,
+	soloed: bool,
+	// a per-track level multiplier, applied on top of the source's master
+	// volume, for balancing the mix between tracks (e.g. a quieter bass
+	// under a louder melody). 1.0 is unity gain
+	gain: f64,
+	// mixes in a sine an octave below each note, scaled by this level, to
+	// fatten thin bass waveforms. 0.0 (the default) disables it
+	sub_level: f64,
+	// stacks detuned copies of each note for a thicker unison sound. None
+	// (the default) plays a single voice per note, as before
+	unison: Option<Unison>,
+	// a waveform queued by `set_waveform`, applied at the next note boundary
+	// rather than immediately. See `play_track`
+	pending_waveform: Option<Waveform>,
+	// cycles a `Chord` instruction's pitches one at a time, `arp_rate` (a
+	// fraction of a whole note, the same units as `Instruction`'s `length`)
+	// apart, instead of sounding them all together. None (the default)
+	// leaves chords sustained as before
+	arp_rate: Option<f64>,
+	arp_pattern: ArpPattern,
+	// seconds owed to the most recently played `Instruction::Grace`, carved
+	// off the start of whichever instruction plays next. Zero the rest of
+	// the time
+	grace_debt: f64,
+	// delays this track's first instruction by this many whole notes (the
+	// same units as `Instruction`'s `length`), so a pickup/anacrusis track
+	// can start partway into the song without padding its instructions
+	// with leading `Rest`s. 0.0 (the default) starts immediately
+	start_offset: f64,
+	// scales this track's own tempo relative to the shared `bpm`, so it can
+	// run a different subdivision/meter concurrently with everything else.
+	// 1.0 (the default) tracks the shared tempo exactly; a track playing
+	// straight quarter notes at 1.0 against another at `4.0 / 3.0` produces
+	// a 3-against-4 polyrhythm, since three of the second track's (longer)
+	// beats span the same wall-clock time as four of the first's
+	polyrhythm: f64,
+	// shifts this track's oscillator phase by this fraction of a cycle
+	// (0.0..1.0) before the waveform is sampled, so two tracks doubling the
+	// same pitch don't start each cycle in lockstep and comb-filter each
+	// other when mixed. 0.0 (the default) samples from the same phase as
+	// before
+	phase_offset: f64,
+	// seeds the RNG behind `Instruction::Note`'s `probability`. Two tracks
+	// with the same seed and the same notation reproduce the same sequence
+	// of decisions; vary it with `with_seed` to decorrelate them
+	probability_seed: u64,
+	// how many times this track has looped back to its start, used to salt
+	// `probability_seed` so a note's probability is re-rolled independently
+	// on each repeat instead of always landing the same way
+	pass: u64,
+	// seconds a note's release is allowed to ring on past its own nominal
+	// slot, overlapping whatever plays next on this track. 0.0 (the default)
+	// keeps a note's full envelope, including release, confined to its own
+	// slot as before. See `with_release_tail` and `tail_voice`
+	release_tail: f64,
+	// a note that has moved past its nominal slot but is still ringing out
+	// through `release_tail`, mixed in on top of whatever plays next. `None`
+	// once the tail finishes decaying, or whenever `release_tail` is 0.0
+	tail_voice: Option<TailVoice>,
+	// the swing/humanize delay and real length `play_track` last worked out
+	// for `current_instruction`, so the thousands of samples spent inside a
+	// single instruction's slot don't redo that bookkeeping on every one of
+	// them. Invalidated (and recomputed) whenever `current_instruction` or
+	// `start_of_instruction` has moved on since. See `InstructionTiming`
+	instruction_timing: Option<InstructionTiming>,
+	// the most recent `Instruction::Crescendo`'s ramp, still scaling every
+	// `Note`'s velocity even after `duration` has elapsed (it just holds at
+	// `to_velocity` from then on). `None` before the track's first crescendo,
+	// meaning no scaling at all. See `DynamicRamp`
+	active_crescendo: Option<DynamicRamp>,
+}
+
+// a linear ramp of a track's overall dynamic level, started by an
+// `Instruction::Crescendo`. Evaluated against the track's own absolute local
+// time rather than tracked per-note, so it scales smoothly through note
+// boundaries and even mid-note
+struct DynamicRamp {
+	from_velocity: f64,
+	to_velocity: f64,
+	start: f64,
+	duration: f64,
+}
+
+// this track's dynamic-ramp multiplier at time `t` (the track's own local
+// time): 1.0 with no crescendo yet, linearly interpolating from the level in
+// effect when the most recent one started to its `to_velocity` over
+// `duration`, then holding at `to_velocity` once elapsed
+fn dynamic_scale(ramp: &Option<DynamicRamp>, t: f64) -> f64 {
+	let ramp = match ramp {
+		Some(ramp) => ramp,
+		None => return 1.0,
+	};
+
+	let fraction = if ramp.duration > 0.0 {
+		((t - ramp.start) / ramp.duration).max(0.0).min(1.0)
+	} else {
+		1.0
+	};
+
+	ramp.from_velocity + (ramp.to_velocity - ramp.from_velocity) * fraction
+}
+
+// the per-instruction values `play_track` derives from `current_instruction`/
+// `start_of_instruction` before it can evaluate any actual sound: how long
+// this instruction's slot lasts, and the swing/humanize delay pushing its
+// downbeat off the nominal grid. All of it is constant for as long as
+// `current_instruction` doesn't change AND `measure_time` doesn't change
+// underneath it — `bpm` is shared across every track on a `Source`, so a
+// `Tempo` instruction on another track can change it out from under a track
+// that's still mid-note, unlike the oscillator/envelope below, which still
+// depends on `t` freshly every sample
+struct InstructionTiming {
+	current_instruction: usize,
+	start_of_instruction: f64,
+	measure_time: f64,
+	current_length: f64,
+	delay: f64,
+	humanize_seed: u64,
+}
+
+// a snapshot of a note taken the instant it moves past its own nominal slot,
+// just enough to keep rendering its release after `play_track` has already
+// moved on to the next instruction. See `Track::release_tail`
+struct TailVoice {
+	pitch: i32,
+	velocity: f64,
+	waveform: Waveform,
+	adsr: Adsr,
+	tremolo: Option<Tremolo>,
+	sub_level: f64,
+	unison: Option<Unison>,
+	phase_offset: f64,
+	// this voice's own local time (matching the note's original `t == 0` at
+	// the start of its slot) at which its nominal slot ended and the release
+	// tail began
+	gated_length: f64,
+	release_tail: f64,
+	// the local time, in the track's own timeline, at which this note's slot
+	// started; subtracted from `play_track`'s `t` to recover the voice's own
+	// local time
+	start: f64,
+}
+
+impl Track {
+	pub fn new(instructions: Vec<Instruction>, waveform: Waveform, adsr: Adsr, pan: f64) -> Self {
+		Track {
+			instructions,
+			waveform,
+			adsr,
+			pan,
+			vibrato: None,
+			tremolo: None,
+			portamento_ms: 0.0,
+			previous_pitch: None,
+			start_of_instruction: 0.0,
+			current_instruction: 0,
+			muted: false,
+			soloed: false,
+			gain: 1.0,
+			sub_level: 0.0,
+			unison: None,
+			pending_waveform: None,
+			arp_rate: None,
+			arp_pattern: ArpPattern::Up,
+			grace_debt: 0.0,
+			start_offset: 0.0,
+			polyrhythm: 1.0,
+			phase_offset: 0.0,
+			probability_seed: 0,
+			pass: 0,
+			release_tail: 0.0,
+			tail_voice: None,
+			instruction_timing: None,
+			active_crescendo: None,
+		}
+	}
+
+	// lets a note's release ring on for up to `release_tail` seconds past its
+	// own nominal slot, overlapping the note that plays after it, instead of
+	// being cut off exactly at the slot boundary. While a tail rings, the
+	// note's own release stage is deferred to it entirely (its slot holds at
+	// the sustain level throughout); 0.0 (the default) disables this and
+	// keeps release confined to the note's own slot, as before
+	pub fn with_release_tail(mut self, release_tail: f64) -> Self {
+		self.release_tail = release_tail;
+
+		self
+	}
+
+	// scales this track's own tempo relative to the shared `bpm`, letting it
+	// run a different subdivision/meter concurrently with the rest of the
+	// source. See the `polyrhythm` field
+	pub fn with_polyrhythm(mut self, polyrhythm: f64) -> Self {
+		self.polyrhythm = polyrhythm;
+
+		self
+	}
+
+	// shifts this track's oscillator phase by `phase_offset` (a fraction of a
+	// cycle, 0.0..1.0). See the `phase_offset` field
+	pub fn with_phase_offset(mut self, phase_offset: f64) -> Self {
+		self.phase_offset = phase_offset;
+
+		self
+	}
+
+	// seeds this track's `Instruction::Note` probability RNG. See the
+	// `probability_seed` field
+	pub fn with_seed(mut self, seed: u64) -> Self {
+		self.probability_seed = seed;
+
+		self
+	}
+
+	// delays this track's entrance by `start_offset` whole notes, for a
+	// pickup/anacrusis part that shouldn't sound until partway into the
+	// song. See the `start_offset` field
+	pub fn with_start_offset(mut self, start_offset: f64) -> Self {
+		self.start_offset = start_offset;
+
+		self
+	}
+
+	// attaches vibrato to every note played on this track
+	pub fn with_vibrato(mut self, vibrato: Vibrato) -> Self {
+		self.vibrato = Some(vibrato);
+
+		self
+	}
+
+	// attaches tremolo to every note played on this track
+	pub fn with_tremolo(mut self, tremolo: Tremolo) -> Self {
+		self.tremolo = Some(tremolo);
+
+		self
+	}
+
+	// glides each new note's pitch from the previous note's over
+	// `portamento_ms` milliseconds, a synth-lead staple. Distinct from an
+	// explicit `Instruction::Slide`, this applies automatically between
+	// consecutive notes. 0.0 (the default) disables it
+	pub fn with_portamento(mut self, portamento_ms: f64) -> Self {
+		self.portamento_ms = portamento_ms;
+
+		self
+	}
+
+	// silences this track entirely; overridden by `with_soloed` on other
+	// tracks only in the sense that a muted track never sounds regardless
+	pub fn with_muted(mut self, muted: bool) -> Self {
+		self.muted = muted;
+
+		self
+	}
+
+	// marks this track as soloed: once any track in a `Source` is soloed,
+	// only soloed tracks sound
+	pub fn with_soloed(mut self, soloed: bool) -> Self {
+		self.soloed = soloed;
+
+		self
+	}
+
+	// scales this track's output by `gain` before it's summed with the rest
+	// of the source, independent of the master volume. 1.0 (the default) is
+	// unity gain
+	pub fn with_gain(mut self, gain: f64) -> Self {
+		self.gain = gain;
+
+		self
+	}
+
+	// mixes a sine wave one octave below each note into this track's signal,
+	// scaled by `sub_level`, to add low-end weight below the main waveform.
+	// 0.0 (the default) disables it; values above 1.0 let the sub-octave
+	// dominate the note's own waveform
+	pub fn with_sub_level(mut self, sub_level: f64) -> Self {
+		self.sub_level = sub_level;
+
+		self
+	}
+
+	// stacks `unison.voices` detuned copies of each note, summed and
+	// averaged back to unity gain, for a thicker "supersaw"-style sound
+	pub fn with_unison(mut self, unison: Unison) -> Self {
+		self.unison = Some(unison);
+
+		self
+	}
+
+	// applies a bundled waveform/envelope/texture preset in one call, instead
+	// of setting `waveform`/`adsr` at construction and then `with_vibrato`/
+	// `with_tremolo`/`with_sub_level`/`with_unison` individually. Every field
+	// `instrument` carries (including `None`s) overwrites this track's
+	// existing value, so apply it before any other builder call that should
+	// win
+	pub fn with_instrument(mut self, instrument: Instrument) -> Self {
+		self.waveform = instrument.waveform;
+		self.adsr = instrument.adsr;
+		self.vibrato = instrument.vibrato;
+		self.tremolo = instrument.tremolo;
+		self.sub_level = instrument.sub_level;
+		self.unison = instrument.unison;
+
+		self
+	}
+
+	// queues a live waveform switch, applied at this track's next note
+	// boundary rather than immediately (see `play_track`). Unlike the
+	// `with_*` builders, this is meant to be called on an already-playing
+	// track, e.g. from a keyboard shortcut cycling through timbres
+	pub fn set_waveform(&mut self, waveform: Waveform) {
+		self.pending_waveform = Some(waveform);
+	}
+
+	// arpeggiates this track's chords instead of sounding every pitch at
+	// once: `rate` (a fraction of a whole note, e.g. `N32ND`) is how long
+	// each pitch gets before advancing to the next one, in `pattern` order
+	pub fn with_arpeggio(mut self, rate: f64, pattern: ArpPattern) -> Self {
+		self.arp_rate = Some(rate);
+		self.arp_pattern = pattern;
+
+		self
+	}
+}
+
+// one of the built-in kit sounds a `DrumTrack` can play, synthesized
+// directly rather than through the pitched `Waveform` enum
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum Drum {
+	Kick,
+	Snare,
+	HiHat,
+}
+
+// a `Track`-like timeline, but its instructions name a `Drum` instead of
+// carrying a pitch
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum DrumInstruction {
+	Hit {drum: Drum, length: f64},
+	Rest {length: f64},
+}
+
+impl DrumInstruction {
+	fn length(&self) -> f64 {
+		match self {
+			DrumInstruction::Hit {length, ..} => *length,
+			DrumInstruction::Rest {length} => *length,
+		}
+	}
+}
+
+// a dedicated percussion track: instructions reference drum sounds by name
+// rather than pitch, and mix into a `Source` alongside its pitched `Track`s
+pub struct DrumTrack {
+	instructions: Vec<DrumInstruction>,
+	pan: f64,
+	start_of_instruction: f64,
+	current_instruction: usize,
+	muted: bool,
+	soloed: bool,
+}
+
+impl DrumTrack {
+	pub fn new(instructions: Vec<DrumInstruction>, pan: f64) -> Self {
+		DrumTrack {
+			instructions,
+			pan,
+			start_of_instruction: 0.0,
+			current_instruction: 0,
+			muted: false,
+			soloed: false,
+		}
+	}
+
+	// silences this track entirely, the same as `Track::with_muted`
+	pub fn with_muted(mut self, muted: bool) -> Self {
+		self.muted = muted;
+
+		self
+	}
+
+	// marks this track as soloed, the same as `Track::with_soloed`. Solo
+	// status is tracked separately from pitched tracks: soloing a drum
+	// track never silences the pitched tracks and vice versa
+	pub fn with_soloed(mut self, soloed: bool) -> Self {
+		self.soloed = soloed;
+
+		self
+	}
+}
+
+pub struct Source {
+	tracks: Vec<Track>,
+	drum_tracks: Vec<DrumTrack>,
+	bpm: f64,
+	// number of times to repeat after the first playthrough; None loops forever
+	loop_count: Option<u32>,
+	volume: f64,
+	low_pass: Option<LowPass>,
+	delay: Option<Delay>,
+	reverb: Option<Reverb>,
+	// 0.0 is straight timing; ~0.33 approximates triplet swing. Applied to
+	// every off-beat eighth note across every track
+	swing: f64,
+	// the frequency, in Hz, that pitch offset 0 (A4) resolves to. 440.0 is
+	// concert pitch; historical or alternate tunings use other references
+	tuning: f64,
+	temperament: Temperament,
+	// 0.0 disables crossfading; otherwise the last `crossfade_ms` of an
+	// instruction are faded out while the next instruction is faded in over
+	// the same window, smoothing over clicks at instruction boundaries
+	crossfade_ms: f64,
+	time_signature: TimeSignature,
+	// a global pitch offset in semitones applied to every currently
+	// sounding note, meant to be driven live by an incoming MIDI
+	// pitch-bend message rather than set once at construction
+	bend_semitones: f64,
+	// caps how many tracks/drum hits may sound at once; None is unbounded.
+	// See `play_source` for the stealing policy applied once this is exceeded
+	max_voices: Option<u32>,
+	mix_mode: MixMode,
+	pan_law: PanLaw,
+	// 0.0 is fully mechanical; higher values nudge each note's onset and
+	// velocity by a small, bounded, reproducible amount so playback doesn't
+	// sound quantized. See `humanize_jitter`
+	humanize: f64,
+	// `play_source`'s absolute `t` at the start of the current loop pass,
+	// subtracted back off before it reaches any track. Without this, a song
+	// left looping for hours keeps subtracting two nearly-equal, ever-larger
+	// `f64`s (`t` and `start_of_instruction`) to recover a note's local time,
+	// which loses precision as both operands grow; rebasing at each loop
+	// keeps every value tracks actually see bounded to one loop's length
+	time_origin: f64,
+	// counts down the samples of brief silence a `Source` with no
+	// instructions anywhere plays before `play_source` starts returning
+	// `None`. `None` until the first call discovers there's nothing to
+	// play, at which point it's set to `EMPTY_SOURCE_SILENCE_MS` worth of
+	// samples at that call's sample rate
+	empty_silence_remaining: Option<u32>,
+}
+
+// how a track's mono signal and `pan` value are distributed across
+// left/right. Consulted wherever `pan()` is called, i.e. everywhere a
+// track's (or ring-modulated pair's) signal joins the final mix
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum PanLaw {
+	// sum-to-one: amplitude splits proportionally to `pan`, so a centered
+	// signal sits at half amplitude per channel and total perceived loudness
+	// dips slightly as a sound crosses the middle
+	Linear,
+	// -3dB center: uses sin/cos of the pan angle so total power stays
+	// constant regardless of position. The historical (and default) behavior
+	ConstantPower,
+}
+
+// how pitched tracks are combined into the final signal
+#[cfg_attr(feature = "serde-support", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-support", serde(tag = "type"))]
+pub enum MixMode {
+	// every track is summed together, the historical (and default) behavior
+	Sum,
+	// the tracks at these two indices into `Source`'s track list are
+	// multiplied together instead of summed, producing the metallic,
+	// inharmonic tone of ring modulation; every other track still sums
+	// normally. If one side is resting (or the source has no track at that
+	// index) the other passes through unmodified; if both are, the pair
+	// contributes silence rather than a spurious constant signal
+	RingMod {track_a: usize, track_b: usize},
+}
+
+// the default master volume applied when rendering a source to samples,
+// whether live or to a wav file
+const DEFAULT_VOLUME: f64 = 0.1;
+
+// concert pitch: the frequency of A4 under the standard tuning reference
+const DEFAULT_TUNING: f64 = 440.0;
+
+impl Source {
+	pub fn new(tracks: Vec<Track>, bpm: f64, loop_count: Option<u32>) -> Self {
+		if tracks.is_empty() {
+			eprintln!("warning: Source::new was given no tracks; play_source will produce silence");
+		}
+
+		Source {
+			tracks,
+			drum_tracks: Vec::new(),
+			bpm,
+			loop_count,
+			volume: DEFAULT_VOLUME,
+			low_pass: None,
+			delay: None,
+			reverb: None,
+			swing: 0.0,
+			tuning: DEFAULT_TUNING,
+			temperament: Temperament::EqualTemperament,
+			crossfade_ms: 0.0,
+			time_signature: TimeSignature::default(),
+			bend_semitones: 0.0,
+			max_voices: None,
+			mix_mode: MixMode::Sum,
+			pan_law: PanLaw::ConstantPower,
+			humanize: 0.0,
+			time_origin: 0.0,
+			empty_silence_remaining: None,
+		}
+	}
+
+	// overrides the master volume; callers should clamp to 0.0..=1.0 themselves
+	pub fn with_volume(mut self, volume: f64) -> Self {
+		self.volume = volume;
+
+		self
+	}
+
+	// softens the mixed output with a one-pole low-pass filter at `cutoff_hz`
+	pub fn with_low_pass(mut self, cutoff_hz: f64) -> Self {
+		self.low_pass = Some(LowPass {cutoff_hz, previous: (0.0, 0.0)});
+
+		self
+	}
+
+	// echoes the mixed output through a delay line `time_ms` long. `feedback`
+	// is clamped below 1.0 to avoid a runaway buildup, `mix` is how much of
+	// the delayed signal is blended back into the output
+	pub fn with_delay(mut self, time_ms: f64, feedback: f64, mix: f64) -> Self {
+		self.delay = Some(Delay {
+			time_ms,
+			feedback: feedback.min(0.99),
+			mix,
+			buffer: Vec::new(),
+			position: 0,
+		});
+
+		self
+	}
+
+	// adds a Schroeder-style algorithmic reverb to the mixed output: a bank
+	// of parallel comb filters feeding two allpass filters in series,
+	// smoothing the combs' periodic ringing into a diffuse tail. `mix` is
+	// how much of the reverberated signal is blended back into the dry
+	// output, clamped below 1.0 to leave the dry signal audible
+	pub fn with_reverb(mut self, mix: f64) -> Self {
+		self.reverb = Some(Reverb::new(mix.min(0.99)));
+
+		self
+	}
+
+	// adds percussion tracks, mixed in alongside the pitched `Track`s. See
+	// `DrumTrack` for the built-in kit
+	pub fn with_drum_tracks(mut self, drum_tracks: Vec<DrumTrack>) -> Self {
+		self.drum_tracks = drum_tracks;
+
+		self
+	}
+
+	// caps the number of tracks/drum hits that may sound at once, silencing
+	// the quietest ones once the cap is exceeded. Voices are counted per
+	// `Track`/`DrumTrack`, not per note within a chord, since chord notes are
+	// already mixed down before this point. See `play_source` for the
+	// stealing policy
+	pub fn with_max_voices(mut self, max_voices: u32) -> Self {
+		self.max_voices = Some(max_voices);
+
+		self
+	}
+
+	// changes how tracks are combined; see `MixMode`
+	pub fn with_mix_mode(mut self, mix_mode: MixMode) -> Self {
+		self.mix_mode = mix_mode;
+
+		self
+	}
+
+	// changes how a track's `pan` distributes its signal across left/right;
+	// see `PanLaw`
+	pub fn with_pan_law(mut self, pan_law: PanLaw) -> Self {
+		self.pan_law = pan_law;
+
+		self
+	}
+
+	// delays every off-beat eighth note by `swing` of an eighth note's
+	// length, giving a shuffled/jazzy feel. 0.0 (the default) is straight
+	// timing; ~0.33 approximates a triplet swing
+	pub fn with_swing(mut self, swing: f64) -> Self {
+		self.swing = swing;
+
+		self
+	}
+
+	// nudges each note's onset and velocity by a small, bounded, reproducible
+	// amount so playback doesn't sound quantized. 0.0 (the default) is fully
+	// mechanical; 1.0 is the largest jitter that's still guaranteed not to
+	// reorder adjacent notes
+	pub fn with_humanize(mut self, humanize: f64) -> Self {
+		self.humanize = humanize;
+
+		self
+	}
+
+	// overrides the tuning reference (the frequency A4 resolves to); 440.0
+	// is concert pitch, some ensembles tune to 442.0, and 432.0 is a common
+	// alternate reference
+	pub fn with_tuning(mut self, tuning: f64) -> Self {
+		self.tuning = tuning;
+
+		self
+	}
+
+	// selects the temperament used to map pitch offsets to frequency ratios;
+	// the default is standard 12-tone equal temperament
+	pub fn with_temperament(mut self, temperament: Temperament) -> Self {
+		self.temperament = temperament;
+
+		self
+	}
+
+	// crossfades the last `crossfade_ms` of each instruction into the next
+	// one, smoothing over clicks where two notes don't both reach zero at
+	// the boundary. 0.0 (the default) disables it
+	pub fn with_crossfade(mut self, crossfade_ms: f64) -> Self {
+		self.crossfade_ms = crossfade_ms;
+
+		self
+	}
+
+	// sets the time signature, used to compute beat and measure durations
+	// for beat-based features like the metronome. Defaults to 4/4
+	pub fn with_time_signature(mut self, time_signature: TimeSignature) -> Self {
+		self.time_signature = time_signature;
+
+		self
+	}
+
+	// appends a trailing `Instruction::Rest` to every track shorter than the
+	// longest one, so every track ends together instead of dropping out
+	// early and leaving the rest of the mix playing alone. Uses the same
+	// nominal per-track total (in whole notes, tempo changes aside) as
+	// `validate`, so this is opt-in rather than automatic: a track that's
+	// deliberately shorter (a pickup, an intentional early exit) would
+	// otherwise get silently padded too
+	pub fn with_gapless_padding(mut self) -> Self {
+		let totals: Vec<f64> = self.tracks.iter()
+			.map(|track| track.instructions.iter().map(Instruction::length).sum())
+			.collect();
+
+		let longest = totals.iter().cloned().fold(0.0, f64::max);
+
+		for (track, total) in self.tracks.iter_mut().zip(totals) {
+			let padding = longest - total;
+
+			if padding > 0.0 {
+				track.instructions.push(Instruction::Rest {length: padding});
+			}
+		}
+
+		self
+	}
+
+	// the duration, in seconds, of one beat (a `denominator`-th note) at the
+	// source's current tempo and time signature
+	pub fn beat_seconds(&self) -> f64 {
+		beat_seconds(self.bpm, &self.time_signature)
+	}
+
+	// the duration, in seconds, of one full measure at the source's current
+	// tempo and time signature
+	pub fn measure_seconds(&self) -> f64 {
+		self.time_signature.numerator as f64 * self.beat_seconds()
+	}
+
+	// the number of beats per measure, e.g. 3 for 3/4 or 6 for 6/8
+	pub fn beats_per_measure(&self) -> u32 {
+		self.time_signature.numerator
+	}
+
+	// shifts every note across every track by `semitones`, leaving rests
+	// untouched. `pitch_compute` is exponential in the pitch offset, so
+	// there's no risk of underflow even for a large downward transpose
+	pub fn transpose(&mut self, semitones: i32) {
+		for track in self.tracks.iter_mut() {
+			for instruction in track.instructions.iter_mut() {
+				match instruction {
+					Instruction::Note {pitch, ..} => *pitch += semitones,
+					Instruction::Chord {pitches, ..} => {
+						for pitch in pitches.iter_mut() {
+							*pitch += semitones;
+						}
+					},
+					Instruction::Slide {from, to, ..} => {
+						*from += semitones;
+						*to += semitones;
+					},
+					Instruction::Grace {pitch} => *pitch += semitones,
+					Instruction::Rest {..} | Instruction::Tempo {..} | Instruction::Crescendo {..} => {},
+				}
+			}
+		}
+	}
+
+	// sets the live pitch-bend offset in semitones, applied on top of every
+	// currently sounding note until changed again. Unlike `transpose`, this
+	// doesn't touch the instructions themselves, so it's cheap to call every
+	// buffer callback from a MIDI pitch-bend handler
+	pub fn set_bend_semitones(&mut self, semitones: f64) {
+		self.bend_semitones = semitones;
+	}
+
+	// queues a live waveform switch on every track, for e.g. cycling through
+	// timbres from a keyboard shortcut while a song plays. Like
+	// `Track::set_waveform`, each track only actually swaps over at its own
+	// next note boundary, so a switch mid-note never chops its waveform
+	pub fn set_waveform(&mut self, waveform: Waveform) {
+		for track in self.tracks.iter_mut() {
+			track.set_waveform(waveform);
+		}
+	}
+
+	// jumps every track's and drum track's instruction cursor to wherever
+	// they'd be `seconds` into playback, so the next `play_source` call
+	// resumes from there instead of the top. Tempo changes on the
+	// seeked-past instructions are replayed so `self.bpm` ends up correct;
+	// tracks are processed one at a time rather than in strict real-time
+	// order, so if more than one track changes tempo independently, the
+	// result is only an approximation of what real-time playback would do
+	pub fn seek(&mut self, seconds: f64) {
+		let mut bpm = self.bpm;
+
+		for track in self.tracks.iter_mut() {
+			let start_offset_seconds = track.start_offset * (60.0 / bpm * 4.0 * track.polyrhythm);
+			let track_seconds = (seconds - start_offset_seconds).max(0.0);
+
+			seek_track(
+				&track.instructions, &mut track.current_instruction,
+				&mut track.start_of_instruction, &mut track.previous_pitch,
+				&mut track.grace_debt, track_seconds, &mut bpm, track.polyrhythm,
+			);
+		}
+
+		self.bpm = bpm;
+
+		for track in self.drum_tracks.iter_mut() {
+			seek_drum_track(
+				&track.instructions, &mut track.current_instruction,
+				&mut track.start_of_instruction, seconds, self.bpm,
+			);
+		}
+	}
+
+	// reports one warning per track whose total nominal length (in whole
+	// notes, tempo changes aside) differs from the longest track by more
+	// than a sixteenth note. That usually signals a transcription mistake
+	// rather than an intentional pickup or tail, but playback isn't blocked
+	// on it either way
+	pub fn validate(&self) -> Vec<String> {
+		let totals: Vec<f64> = self.tracks.iter()
+			.map(|track| track.instructions.iter().map(Instruction::length).sum())
+			.collect();
+
+		let longest = totals.iter().cloned().fold(0.0, f64::max);
+
+		totals.iter().enumerate()
+			.filter(|(_, &total)| (longest - total).abs() > N16TH)
+			.map(|(i, &total)| format!(
+				"track {} has total length {:.4} whole notes, longest track has {:.4}",
+				i, total, longest,
+			))
+			.collect()
+	}
+
+	// decouples synthesis from any particular playback backend: yields the
+	// post-`play_source` mono samples one at a time, advancing the same
+	// counter-based time the live cpal path and `render_to_wav` both use
+	pub fn samples(&mut self, sample_rate: u32) -> Samples<'_> {
+		Samples {source: self, sample_rate, counter: 0}
+	}
+
+	// like `samples`, but keeps left/right separate instead of collapsing them
+	// to mono. See `render_to_wav`
+	pub fn stereo_samples(&mut self, sample_rate: u32) -> StereoSamples<'_> {
+		StereoSamples {source: self, sample_rate, counter: 0}
+	}
+}
+
+// see `Source::samples`
+pub struct Samples<'a> {
+	source: &'a mut Source,
+	sample_rate: u32,
+	counter: u64,
+}
+
+impl<'a> Iterator for Samples<'a> {
+	type Item = f64;
+
+	fn next(&mut self) -> Option<f64> {
+		let t = (self.counter as f64) / (self.sample_rate as f64);
+		let (left, right) = play_source(t, self.source, self.sample_rate)?;
+
+		self.counter += 1;
+
+		Some((left + right) * 0.5)
+	}
+}
+
+// see `Source::stereo_samples`
+pub struct StereoSamples<'a> {
+	source: &'a mut Source,
+	sample_rate: u32,
+	counter: u64,
+}
+
+impl<'a> Iterator for StereoSamples<'a> {
+	type Item = (f64, f64);
+
+	fn next(&mut self) -> Option<(f64, f64)> {
+		let t = (self.counter as f64) / (self.sample_rate as f64);
+		let sample = play_source(t, self.source, self.sample_rate)?;
+
+		self.counter += 1;
+
+		Some(sample)
+	}
+}
+
+// one-pole low-pass filter state. `previous` is the filter's last output,
+// which has to persist across calls to `play_source` (and so across the
+// many small cpal buffer callbacks) or the filter would reset every buffer
+struct LowPass {
+	cutoff_hz: f64,
+	previous: (f64, f64),
+}
+
+impl LowPass {
+	fn apply(&mut self, signal: (f64, f64), sample_rate: u32) -> (f64, f64) {
+		let dt = 1.0 / f64::from(sample_rate);
+		let rc = 1.0 / (TAU * self.cutoff_hz);
+		let alpha = dt / (rc + dt);
+
+		let (prev_l, prev_r) = self.previous;
+		let (l, r) = signal;
+
+		let output = (
+			prev_l + alpha * (l - prev_l),
+			prev_r + alpha * (r - prev_r),
+		);
+
+		self.previous = output;
+
+		output
+	}
+}
+
+// circular delay buffer for a simple echo effect. `buffer` is allocated
+// lazily on the first sample, once the sample rate is known, and persists
+// across calls to `play_source` the same way `LowPass::previous` does
+struct Delay {
+	time_ms: f64,
+	feedback: f64,
+	mix: f64,
+	buffer: Vec<(f64, f64)>,
+	position: usize,
+}
+
+impl Delay {
+	fn apply(&mut self, signal: (f64, f64), sample_rate: u32) -> (f64, f64) {
+		if self.buffer.is_empty() {
+			let sample_count = (self.time_ms / 1000.0 * f64::from(sample_rate)) as usize;
+
+			self.buffer = vec![(0.0, 0.0); sample_count.max(1)];
+		}
+
+		let (l, r) = signal;
+		let (delayed_l, delayed_r) = self.buffer[self.position];
+
+		self.buffer[self.position] = (l + delayed_l * self.feedback, r + delayed_r * self.feedback);
+		self.position = (self.position + 1) % self.buffer.len();
+
+		(l + delayed_l * self.mix, r + delayed_r * self.mix)
+	}
+}
+
+// one tap of a Schroeder comb filter: delay line with feedback, buffer
+// allocated lazily the same way `Delay`'s is
+struct CombFilter {
+	delay_ms: f64,
+	feedback: f64,
+	buffer: Vec<(f64, f64)>,
+	position: usize,
+}
+
+impl CombFilter {
+	fn apply(&mut self, input: (f64, f64), sample_rate: u32) -> (f64, f64) {
+		if self.buffer.is_empty() {
+			let sample_count = (self.delay_ms / 1000.0 * f64::from(sample_rate)) as usize;
+
+			self.buffer = vec![(0.0, 0.0); sample_count.max(1)];
+		}
+
+		let (in_l, in_r) = input;
+		let (out_l, out_r) = self.buffer[self.position];
+
+		self.buffer[self.position] = (in_l + out_l * self.feedback, in_r + out_r * self.feedback);
+		self.position = (self.position + 1) % self.buffer.len();
+
+		(out_l, out_r)
+	}
+}
+
+// one stage of a Schroeder allpass filter: passes all frequencies at equal
+// gain while smearing the comb filters' periodic ringing out in time
+struct AllpassFilter {
+	delay_ms: f64,
+	feedback: f64,
+	buffer: Vec<(f64, f64)>,
+	position: usize,
+}
+
+impl AllpassFilter {
+	fn apply(&mut self, input: (f64, f64), sample_rate: u32) -> (f64, f64) {
+		if self.buffer.is_empty() {
+			let sample_count = (self.delay_ms / 1000.0 * f64::from(sample_rate)) as usize;
+
+			self.buffer = vec![(0.0, 0.0); sample_count.max(1)];
+		}
+
+		let (in_l, in_r) = input;
+		let (buf_l, buf_r) = self.buffer[self.position];
+
+		let output = (buf_l - self.feedback * in_l, buf_r - self.feedback * in_r);
+
+		self.buffer[self.position] = (in_l + buf_l * self.feedback, in_r + buf_r * self.feedback);
+		self.position = (self.position + 1) % self.buffer.len();
+
+		output
+	}
+}
+
+// comb delay lengths, in milliseconds, chosen with no common factors so
+// their resonances don't reinforce each other and produce an audible pitch
+const COMB_DELAYS_MS: [f64; 4] = [29.7, 37.1, 41.1, 43.7];
+const COMB_FEEDBACK: f64 = 0.84;
+const ALLPASS_DELAYS_MS: [f64; 2] = [5.0, 1.7];
+const ALLPASS_FEEDBACK: f64 = 0.5;
+
+// a basic Schroeder reverb: several parallel comb filters are summed and
+// fed through two allpass filters in series, turning the combs' periodic
+// ringing into a diffuse tail. All delay buffers persist across calls to
+// `play_source` the same way `Delay::buffer` does
+struct Reverb {
+	mix: f64,
+	combs: Vec<CombFilter>,
+	allpasses: Vec<AllpassFilter>,
+}
+
+impl Reverb {
+	fn new(mix: f64) -> Self {
+		Reverb {
+			mix,
+			combs: COMB_DELAYS_MS.iter().map(|&delay_ms| CombFilter {
+				delay_ms,
+				feedback: COMB_FEEDBACK,
+				buffer: Vec::new(),
+				position: 0,
+			}).collect(),
+			allpasses: ALLPASS_DELAYS_MS.iter().map(|&delay_ms| AllpassFilter {
+				delay_ms,
+				feedback: ALLPASS_FEEDBACK,
+				buffer: Vec::new(),
+				position: 0,
+			}).collect(),
+		}
+	}
+
+	fn apply(&mut self, signal: (f64, f64), sample_rate: u32) -> (f64, f64) {
+		let mut wet = (0.0, 0.0);
+
+		for comb in self.combs.iter_mut() {
+			let (comb_l, comb_r) = comb.apply(signal, sample_rate);
+
+			wet = (wet.0 + comb_l, wet.1 + comb_r);
+		}
+
+		wet = (wet.0 / self.combs.len() as f64, wet.1 / self.combs.len() as f64);
+
+		for allpass in self.allpasses.iter_mut() {
+			wet = allpass.apply(wet, sample_rate);
+		}
+
+		let (l, r) = signal;
+		let (wet_l, wet_r) = wet;
+
+		(l + (wet_l - l) * self.mix, r + (wet_r - r) * self.mix)
+	}
+}
+
+// how long a `Source` with no instructions anywhere plays silence before
+// `play_source` starts returning `None`, instead of terminating on the very
+// first sample. A caller streaming straight to a live output would otherwise
+// hear that as an abrupt cutoff (or a click) rather than a clean exit
+const EMPTY_SOURCE_SILENCE_MS: f64 = 100.0;
+
+// returns the summed (left, right) signal across all tracks, panned per
+// track according to `pan_law`, or None once every track has finished.
+// Samples are in roughly -1.0..=1.0, though a busy mix can briefly exceed
+// that range.
+pub fn play_source(t: f64, source: &mut Source, sample_rate: u32) -> Option<(f64, f64)> {
+	loop {
+		let Source {tracks, drum_tracks, bpm, loop_count, volume, low_pass, delay, reverb, swing, tuning, temperament, crossfade_ms, bend_semitones, max_voices, mix_mode, pan_law, humanize, time_origin, empty_silence_remaining, ..} = &mut *source;
+
+		// bounded to the current loop pass rather than the ever-growing
+		// absolute `t`; see `time_origin`'s doc comment
+		let t = t - *time_origin;
+
+		// a source with nothing to play at all (no tracks, or tracks with no
+		// instructions) would otherwise never set `any_playing` and, with an
+		// unbounded `loop_count`, spin the loop below forever trying to
+		// restart tracks that can never produce a sample. Play a brief
+		// silence instead of terminating outright on the first sample
+		let has_any_instructions = tracks.iter().any(|track| !track.instructions.is_empty())
+			|| drum_tracks.iter().any(|track| !track.instructions.is_empty());
+
+		if !has_any_instructions {
+			let remaining = empty_silence_remaining.get_or_insert_with(|| {
+				(EMPTY_SOURCE_SILENCE_MS / 1000.0 * f64::from(sample_rate)) as u32
+			});
+
+			return match remaining.checked_sub(1) {
+				Some(next) => {
+					*remaining = next;
+
+					Some((0.0, 0.0))
+				},
+				None => None,
+			};
+		}
+
+		let mut voices: Vec<(f64, f64)> = Vec::new();
+		let mut any_playing = false;
+		let any_soloed = tracks.iter().any(|track| track.soloed);
+
+		// gain-adjusted, pre-pan output for every track, in track order, so
+		// `MixMode::RingMod` can pull out a specific pair by index; `Sum`
+		// just pans and sums every entry. The second element of each pair is
+		// the sounding note's per-note pan override, if any; `None` falls
+		// back to the track's own `pan`
+		let mut track_outputs: Vec<Option<(f64, Option<f64>)>> = Vec::with_capacity(tracks.len());
+
+		for track in tracks.iter_mut() {
+			// play the track regardless of mute/solo so its instruction
+			// cursor stays in sync with everything else, and so a muted
+			// track re-joining mid-song doesn't produce a stale timestamp
+			let output = play_track(t, track, bpm, sample_rate, *swing, *tuning, temperament, *crossfade_ms, *bend_semitones, *humanize);
+
+			any_playing |= output.is_some();
+
+			let silenced = track.muted || (any_soloed && !track.soloed);
+			let output = if silenced {None} else {output};
+
+			track_outputs.push(output.map(|(signal, pan_override)| (signal * track.gain, pan_override)));
+		}
+
+		match mix_mode {
+			MixMode::Sum => {
+				for (track, output) in tracks.iter().zip(track_outputs.iter()) {
+					if let Some((signal, pan_override)) = output {
+						voices.push(pan(*signal, pan_override.unwrap_or(track.pan), pan_law));
+					}
+				}
+			},
+			MixMode::RingMod {track_a, track_b} => {
+				for (i, (track, output)) in tracks.iter().zip(track_outputs.iter()).enumerate() {
+					if i == *track_a || i == *track_b {
+						continue;
+					}
+
+					if let Some((signal, pan_override)) = output {
+						voices.push(pan(*signal, pan_override.unwrap_or(track.pan), pan_law));
+					}
+				}
+
+				// a side sitting on a `Rest`, or not yet past its
+				// `start_offset`, counts as silent for the pair, the same as
+				// one that has finished
+				let bpm_now = *bpm;
+				let sounding = |index: usize| tracks.get(index).map_or(false, |track| {
+					t >= track.start_offset * (60.0 / bpm_now * 4.0 * track.polyrhythm)
+						&& !matches!(track.instructions.get(track.current_instruction), Some(Instruction::Rest {..}))
+				});
+
+				let a = track_outputs.get(*track_a).copied().flatten().filter(|_| sounding(*track_a));
+				let b = track_outputs.get(*track_b).copied().flatten().filter(|_| sounding(*track_b));
+
+				let combined = match (a, b) {
+					(None, None) => None,
+					(Some((a, pan_override)), None) => Some((a, pan_override)),
+					(None, Some((b, pan_override))) => Some((b, pan_override)),
+					(Some((a, pan_override)), Some((b, _))) => Some((a * b, pan_override)),
+				};
+
+				if let Some((signal, pan_override)) = combined {
+					let pan_value = pan_override.or_else(|| tracks.get(*track_a).map(|track| track.pan)).unwrap_or(0.0);
+
+					voices.push(pan(signal, pan_value, pan_law));
+				}
+			},
+		}
+
+		let any_drum_soloed = drum_tracks.iter().any(|track| track.soloed);
+
+		for track in drum_tracks.iter_mut() {
+			let output = play_drum_track(t, track, *bpm);
+
+			any_playing |= output.is_some();
+
+			let silenced = track.muted || (any_drum_soloed && !track.soloed);
+			let output = if silenced {None} else {output};
+
+			if let Some(signal) = output {
+				voices.push(pan(signal, track.pan, pan_law));
+			}
+		}
+
+		// voice stealing: once more voices are sounding than `max_voices`
+		// allows, drop the quietest ones first, so a loud downbeat isn't
+		// starved out by whatever happened to start earlier
+		if let Some(max_voices) = max_voices {
+			let max_voices = *max_voices as usize;
+
+			if voices.len() > max_voices {
+				// a stray NaN/inf from some pathological mix of parameters
+				// elsewhere shouldn't be able to panic live playback; treat it
+				// as tied with whatever it's compared against instead
+				voices.sort_by(|(l1, r1), (l2, r2)| {
+					(l1.abs() + r1.abs()).partial_cmp(&(l2.abs() + r2.abs())).unwrap_or(std::cmp::Ordering::Equal)
+				});
+
+				voices.drain(.. voices.len() - max_voices);
+			}
+		}
+
+		let final_output = voices.into_iter().fold(None, |acc, (l, r)| match acc {
+			None => Some((l, r)),
+			Some((accumulated_l, accumulated_r)) => Some((accumulated_l + l, accumulated_r + r)),
+		});
+
+		if any_playing {
+			let output = final_output.map(|(l, r)| (l * *volume, r * *volume));
+
+			let output = output.map(|signal| match low_pass {
+				Some(low_pass) => low_pass.apply(signal, sample_rate),
+				None => signal,
+			});
+
+			let output = output.map(|signal| match delay {
+				Some(delay) => delay.apply(signal, sample_rate),
+				None => signal,
+			});
+
+			return output.map(|signal| match reverb {
+				Some(reverb) => reverb.apply(signal, sample_rate),
+				None => signal,
+			});
+		}
+
+		match loop_count {
+			Some(0) => return None,
+			Some(remaining) => *remaining -= 1,
+			None => {},
+		}
+
+		// restart every track exactly at the current sample so there's no
+		// gap (and no click) at the loop boundary. `time_origin` moves up to
+		// the (already rebased) `t`, so the next pass's local time starts
+		// back at 0 rather than resuming from wherever this pass left off
+		*time_origin += t;
+
+		for track in tracks.iter_mut() {
+			track.current_instruction = 0;
+			track.start_of_instruction = 0.0;
+			track.pass += 1;
+			// otherwise `dynamic_scale` would measure the new pass's small `t`
+			// against the old pass's `ramp.start`, snapping back to
+			// `from_velocity` instead of holding at `to_velocity`
+			track.active_crescendo = None;
+		}
+
+		for track in drum_tracks.iter_mut() {
+			track.current_instruction = 0;
+			track.start_of_instruction = 0.0;
+		}
+	}
+}
+
+// a queue of sources played back-to-back, e.g. an album of separate songs.
+// `gap` seconds of silence are inserted between sources
+pub struct Playlist {
+	sources: Vec<Source>,
+	current: usize,
+	gap: f64,
+	// absolute time at which the current source's own `t == 0.0`
+	current_start: f64,
+	// set while sitting in the gap between two sources; None while a source is playing
+	silence_until: Option<f64>,
+}
+
+impl Playlist {
+	pub fn new(sources: Vec<Source>, gap: f64) -> Self {
+		Playlist {sources, current: 0, gap, current_start: 0.0, silence_until: None}
+	}
+}
+
+// advances through `playlist`'s sources, feeding each its own source-relative
+// time so a source started midway through the playlist behaves exactly as it
+// would if played alone. Returns None once every source has finished
+pub fn play_playlist(t: f64, playlist: &mut Playlist, sample_rate: u32) -> Option<(f64, f64)> {
+	loop {
+		if let Some(until) = playlist.silence_until {
+			if t < until {
+				return Some((0.0, 0.0));
+			}
+
+			playlist.silence_until = None;
+			playlist.current_start = until;
+		}
+
+		let source = playlist.sources.get_mut(playlist.current)?;
+
+		match play_source(t - playlist.current_start, source, sample_rate) {
+			Some(signal) => return Some(signal),
+			None => {
+				playlist.current += 1;
+				playlist.silence_until = Some(t + playlist.gap);
+			},
+		}
+	}
+}
+
+// distributes `signal` across left/right according to `pan_law`. `pan` is
+// in -1.0..=1.0, -1.0 hard left, 1.0 hard right
+fn pan(signal: f64, pan: f64, pan_law: &PanLaw) -> (f64, f64) {
+	let pan = pan.max(-1.0).min(1.0);
+
+	match pan_law {
+		PanLaw::Linear => (signal * (1.0 - pan) * 0.5, signal * (1.0 + pan) * 0.5),
+		PanLaw::ConstantPower => {
+			let angle = (pan + 1.0) * 0.25 * PI;
+
+			(signal * angle.cos(), signal * angle.sin())
+		},
+	}
+}
+
+// the duration, in seconds, of one beat (a `denominator`-th note) at `bpm`
+// quarter notes per minute. `bpm` is always quarter-note tempo regardless of
+// the time signature's denominator; only which note value counts as "one
+// beat" changes
+fn beat_seconds(bpm: f64, time_signature: &TimeSignature) -> f64 {
+	60.0 / bpm * (4.0 / f64::from(time_signature.denominator))
+}
+
+// determines how much an instruction's onset should be delayed for swing
+// feel, based on its own nominal (undelayed) position on the eighth-note
+// grid. Even eighths land on the beat and are unaffected; odd eighths land
+// on the off-beat and are pushed back by `swing` of an eighth note's length.
+// The instruction's *end* stays anchored to the grid (its rendered length is
+// shortened by the same delay), so a swung pair of eighths still spans
+// exactly the same time as it would straight, and `swing: 0.0` always
+// returns 0.0, leaving straight playback bit-identical
+fn swing_delay(nominal_start: f64, eighth_length: f64, swing: f64) -> f64 {
+	if swing == 0.0 || eighth_length <= 0.0 {
+		return 0.0;
+	}
+
+	let eighth_index = (nominal_start / eighth_length).round() as i64;
+
+	if eighth_index % 2 != 0 {
+		swing * eighth_length
+	} else {
+		0.0
+	}
+}
+
+// this returns None to signal end of source. tempo changes on the track take
+// zero time, so they're applied and skipped past before anything else runs;
+// bpm lives on Source so a change made by one track affects all of them.
+// `start_of_instruction` tracks the nominal (unswung) timeline so swing
+// delays never drift the grid; each instruction's swing delay is worked out
+// from it (and cached in `instruction_timing`) rather than accumulated
+// the second element of the returned pair is this sample's per-note pan
+// override (see `Instruction::Note`'s `pan` field), taken from whichever
+// instruction is currently sounding; `None` leaves the track's own `pan` in
+// charge, same as before this existed
+fn play_track(t: f64, track: &mut Track, bpm: &mut f64, sample_rate: u32, swing: f64, tuning: f64, temperament: &Temperament, crossfade_ms: f64, bend_semitones: f64, humanize: f64) -> Option<(f64, Option<f64>)> {
+	let polyrhythm = track.polyrhythm;
+	let start_offset_seconds = track.start_offset * (60.0 / *bpm * 4.0 * polyrhythm);
+
+	// not yet past its entrance: silent, but still playing, so `play_source`
+	// doesn't read it as finished
+	if t < start_offset_seconds {
+		return Some((0.0, None));
+	}
+
+	let t = t - start_offset_seconds;
+
+	let instructions = &track.instructions;
+	let adsr = &track.adsr;
+	let vibrato = &track.vibrato;
+	let tremolo = &track.tremolo;
+	let portamento_ms = track.portamento_ms;
+	let sub_level = track.sub_level;
+	let unison = &track.unison;
+	let arp_rate = track.arp_rate;
+	let arp_pattern = track.arp_pattern;
+	let phase_offset = track.phase_offset;
+	let probability_seed = track.probability_seed;
+	let pass = track.pass;
+	let previous_pitch = &mut track.previous_pitch;
+	let start_of_instruction = &mut track.start_of_instruction;
+	let current_instruction = &mut track.current_instruction;
+
+	let measure_time = loop {
+		if *current_instruction >= instructions.len() {
+			return take_tail_value(&mut track.tail_voice, t, tuning, temperament, sample_rate).map(|value| (value, None));
+		}
+
+		if let Instruction::Tempo {bpm: new_bpm} = &instructions[*current_instruction] {
+			*bpm = *new_bpm;
+			*current_instruction += 1;
+
+			continue;
+		}
+
+		if let Instruction::Crescendo {to_velocity, over} = &instructions[*current_instruction] {
+			let measure_time = 60.0 / *bpm * 4.0 * polyrhythm;
+
+			track.active_crescendo = Some(DynamicRamp {
+				from_velocity: dynamic_scale(&track.active_crescendo, *start_of_instruction),
+				to_velocity: *to_velocity,
+				start: *start_of_instruction,
+				duration: (*over * measure_time).max(0.0),
+			});
+
+			*current_instruction += 1;
+
+			continue;
+		}
+
+		// scaled by this track's own `polyrhythm`, so it can run a different
+		// subdivision/meter than the shared `bpm` other tracks follow
+		let measure_time = 60.0 / *bpm * 4.0 * polyrhythm;
+		let current_length = effective_length(&instructions[*current_instruction], track.grace_debt, measure_time);
+
+		if t > *start_of_instruction + current_length {
+			if let Instruction::Note {pitch, velocity, probability, ..} = &instructions[*current_instruction] {
+				*previous_pitch = Some(*pitch);
+
+				// this note's own slot is ending; if it should ring on, spawn
+				// a tail voice to keep rendering its release on top of
+				// whatever plays next, rather than cutting it off here
+				let seed = note_probability_seed(probability_seed, pass, *current_instruction);
+
+				if track.release_tail > 0.0 && note_survives(seed, *probability) {
+					track.tail_voice = Some(TailVoice {
+						pitch: *pitch,
+						velocity: *velocity,
+						waveform: track.waveform,
+						adsr: *adsr,
+						tremolo: *tremolo,
+						sub_level,
+						unison: *unison,
+						phase_offset,
+						gated_length: current_length,
+						release_tail: track.release_tail,
+						start: *start_of_instruction,
+					});
+				}
+			}
+
+			// a grace note owes its own fixed duration to whatever plays
+			// next; anything else clears a debt it just paid off
+			if let Instruction::Grace {pitch} = &instructions[*current_instruction] {
+				*previous_pitch = Some(*pitch);
+				track.grace_debt += current_length;
+			} else {
+				track.grace_debt = 0.0;
+			}
+
+			// a waveform queued by `Track::set_waveform` takes effect right
+			// here, at the boundary between notes, rather than immediately,
+			// so a live switch never chops a note's waveform mid-cycle
+			if let Some(pending) = track.pending_waveform.take() {
+				track.waveform = pending;
+			}
+
+			*start_of_instruction += current_length;
+			*current_instruction += 1;
+
+			continue;
+		}
+
+		break measure_time;
+	};
+
+	let waveform = &track.waveform;
+	let grace_debt = track.grace_debt;
+	let release_tail = track.release_tail;
+
+	// this instruction's slot length and swing/humanize delay only change
+	// when `current_instruction`/`start_of_instruction` (or the shared `bpm`)
+	// do, which is far less often than once per sample; recompute them only
+	// when the cache left over from the last call doesn't match anymore.
+	// Frequency/envelope aren't cached the same way: `instruction_value`'s
+	// vibrato, tremolo, portamento, pitch bend, and arpeggiation all modulate
+	// those continuously against live `t`, so unlike the timing above there's
+	// no single per-note value to precompute without dropping those features
+	let timing_valid = track.instruction_timing.as_ref().map_or(false, |timing| {
+		timing.current_instruction == *current_instruction
+			&& timing.start_of_instruction == *start_of_instruction
+			&& timing.measure_time == measure_time
+	});
+
+	if !timing_valid {
+		let eighth_length = N8TH * measure_time;
+		let nominal_length = effective_length(&instructions[*current_instruction], grace_debt, measure_time);
+
+		// salted so the timing and velocity jitters for the same instruction
+		// are uncorrelated, rather than always nudging both the same direction
+		let humanize_timing = humanize * MAX_HUMANIZE_TIMING_FRACTION * nominal_length
+			* humanize_jitter(*current_instruction as u64 * 2);
+
+		track.instruction_timing = Some(InstructionTiming {
+			current_instruction: *current_instruction,
+			start_of_instruction: *start_of_instruction,
+			measure_time,
+			current_length: nominal_length,
+			delay: swing_delay(*start_of_instruction, eighth_length, swing) + humanize_timing,
+			humanize_seed: *current_instruction as u64 * 2 + 1,
+		});
+	}
+
+	let timing = track.instruction_timing.as_ref().unwrap();
+	let delay = timing.delay;
+	let humanize_seed = timing.humanize_seed;
+	let cached_current_length = timing.current_length;
+	let swung_t = t - *start_of_instruction - delay;
+	let dynamics = dynamic_scale(&track.active_crescendo, t);
+
+	let current_value = instruction_value(
+		swung_t, &instructions[*current_instruction], measure_time, delay,
+		waveform, adsr, vibrato.as_ref(), tremolo.as_ref(), tuning, temperament, sample_rate,
+		portamento_ms, *previous_pitch, bend_semitones, sub_level, unison.as_ref(), arp_rate, arp_pattern, grace_debt,
+		humanize, humanize_seed, phase_offset,
+		note_probability_seed(probability_seed, pass, *current_instruction),
+		release_tail, dynamics,
+	);
+
+	let pan_override = instruction_pan(&instructions[*current_instruction]);
+	let tail_value = take_tail_value(&mut track.tail_voice, t, tuning, temperament, sample_rate).unwrap_or(0.0);
+
+	if crossfade_ms <= 0.0 {
+		return Some((current_value + tail_value, pan_override));
+	}
+
+	let crossfade_seconds = crossfade_ms / 1000.0;
+	let current_length = cached_current_length;
+	let remaining = *start_of_instruction + current_length - t;
+
+	// tempo changes take no time to "play", so skip past any that sit
+	// immediately after the current instruction to find the next one that
+	// actually produces sound
+	let next_index = (*current_instruction + 1 ..)
+		.take_while(|&index| index < instructions.len())
+		.find(|&index| !matches!(instructions[index], Instruction::Tempo {..} | Instruction::Crescendo {..}));
+
+	let next_index = match next_index {
+		Some(index) if remaining < crossfade_seconds => index,
+		_ => return Some((current_value + tail_value, pan_override)),
+	};
+
+	// blend the tail of the current instruction into the head of the next
+	// one; the next instruction is evaluated from its own nominal t=0, which
+	// is still negative here, so its envelope naturally starts from silence
+	let fade_out = (remaining / crossfade_seconds).max(0.0).min(1.0);
+	let next_local_t = t - (*start_of_instruction + current_length);
+
+	let glide_from = instruction_pitch(&instructions[*current_instruction]).or(*previous_pitch);
+
+	// the debt only ever falls on the instruction immediately after a grace
+	// note; a preview further ahead than that owes nothing
+	let next_grace_debt = match &instructions[*current_instruction] {
+		Instruction::Grace {..} if next_index == *current_instruction + 1 => current_length,
+		_ => 0.0,
+	};
+
+	let next_value = instruction_value(
+		next_local_t, &instructions[next_index], measure_time, 0.0,
+		waveform, adsr, vibrato.as_ref(), tremolo.as_ref(), tuning, temperament, sample_rate,
+		portamento_ms, glide_from, bend_semitones, sub_level, unison.as_ref(), arp_rate, arp_pattern, next_grace_debt,
+		humanize, next_index as u64 * 2 + 1, phase_offset,
+		note_probability_seed(probability_seed, pass, next_index),
+		release_tail, dynamics,
+	);
+
+	Some((current_value * fade_out + next_value * (1.0 - fade_out) + tail_value, pan_override))
+}
+
+// a tail voice's contribution at `t` (the track's own local time, matching
+// `play_track`'s), or `None` once its release has fully decayed. Doesn't
+// apply vibrato or portamento, since by this point the note it came from
+// has already finished being the "current" instruction
+fn tail_voice_value(tail: &TailVoice, t: f64, tuning: f64, temperament: &Temperament, sample_rate: u32) -> Option<f64> {
+	let local_t = t - tail.start;
+	let release_t = local_t - tail.gated_length;
+
+	if release_t < 0.0 || release_t > tail.release_tail {
+		return None;
+	}
+
+	let frequency = pitch_compute(tail.pitch, tuning, temperament);
+	let phase_offset_t = tail.phase_offset / frequency;
+
+	let bright = unison_wave(local_t + phase_offset_t, frequency, &tail.waveform, sample_rate, tail.unison.as_ref());
+	let dark = sin_wave(local_t * frequency);
+	let brightness = tail.velocity.max(0.0).min(1.0);
+	let sub = sin_wave(local_t * frequency / 2.0) * tail.sub_level;
+	let raw = dark + (bright - dark) * brightness + sub;
+
+	let tremolo_gain = tail.tremolo.map_or(1.0, |tremolo| {
+		1.0 - tremolo.depth * (0.5 - 0.5 * (TAU * tremolo.rate_hz * local_t).cos())
+	});
+
+	let release_frac = if tail.release_tail > 0.0 {(release_t / tail.release_tail).max(0.0).min(1.0)} else {1.0};
+	let envelope_value = tail.adsr.sustain * (1.0 - release_frac.powf(tail.adsr.curve));
+
+	Some(raw * envelope_value * 0.96f64.powi(tail.pitch) * tail.velocity * tremolo_gain)
+}
+
+// fetches a tail voice's current contribution, clearing it once its release
+// has fully decayed
+fn take_tail_value(tail_voice: &mut Option<TailVoice>, t: f64, tuning: f64, temperament: &Temperament, sample_rate: u32) -> Option<f64> {
+	let value = tail_voice.as_ref().and_then(|tail| tail_voice_value(tail, t, tuning, temperament, sample_rate));
+
+	if value.is_none() {
+		*tail_voice = None;
+	}
+
+	value
+}
+
+// the real length, in seconds, that `instruction` occupies in the timeline.
+// A `Grace` gets a fixed short slot of its own; whatever plays right after
+// one has `grace_debt` seconds carved off its notated length to pay for it,
+// so the pair together take no more time than the follower alone would have
+fn effective_length(instruction: &Instruction, grace_debt: f64, measure_time: f64) -> f64 {
+	match instruction {
+		Instruction::Grace {..} => GRACE_LENGTH * measure_time,
+		_ => (instruction.length() * measure_time - grace_debt).max(0.0),
+	}
+}
+
+// advances `current_instruction`/`start_of_instruction` (and, in passing,
+// `previous_pitch` and `bpm`) to wherever a `Track` would be `seconds` into
+// playback, without producing any audio. Used by `Source::seek`
+fn seek_track(
+	instructions: &[Instruction],
+	current_instruction: &mut usize,
+	start_of_instruction: &mut f64,
+	previous_pitch: &mut Option<i32>,
+	grace_debt: &mut f64,
+	seconds: f64,
+	bpm: &mut f64,
+	polyrhythm: f64,
+) {
+	let mut elapsed = 0.0;
+	let mut index = 0;
+	*grace_debt = 0.0;
+
+	while index < instructions.len() {
+		if let Instruction::Tempo {bpm: new_bpm} = &instructions[index] {
+			*bpm = *new_bpm;
+			index += 1;
+
+			continue;
+		}
+
+		let measure_time = 60.0 / *bpm * 4.0 * polyrhythm;
+		let instruction_length = effective_length(&instructions[index], *grace_debt, measure_time);
+
+		if elapsed + instruction_length > seconds {
+			break;
+		}
+
+		if let Instruction::Note {pitch, ..} = &instructions[index] {
+			*previous_pitch = Some(*pitch);
+		}
+
+		if let Instruction::Grace {pitch} = &instructions[index] {
+			*previous_pitch = Some(*pitch);
+			*grace_debt += instruction_length;
+		} else {
+			*grace_debt = 0.0;
+		}
+
+		elapsed += instruction_length;
+		index += 1;
+	}
+
+	*current_instruction = index;
+	*start_of_instruction = elapsed;
+}
+
+// the `DrumTrack` counterpart to `seek_track`: no tempo changes or
+// portamento state to carry along, since drum hits don't have either
+fn seek_drum_track(
+	instructions: &[DrumInstruction],
+	current_instruction: &mut usize,
+	start_of_instruction: &mut f64,
+	seconds: f64,
+	bpm: f64,
+) {
+	let measure_time = 60.0 / bpm * 4.0;
+	let mut elapsed = 0.0;
+	let mut index = 0;
+
+	while index < instructions.len() {
+		let instruction_length = instructions[index].length() * measure_time;
+
+		if elapsed + instruction_length > seconds {
+			break;
+		}
+
+		elapsed += instruction_length;
+		index += 1;
+	}
+
+	*current_instruction = index;
+	*start_of_instruction = elapsed;
+}
+
+// renders a single instruction's raw output at `local_t` seconds into it.
+// Factored out of `play_track` so a crossfade can evaluate both the current
+// and the upcoming instruction the same way.
+fn instruction_value(
+	local_t: f64,
+	instruction: &Instruction,
+	measure_time: f64,
+	delay: f64,
+	waveform: &Waveform,
+	adsr: &Adsr,
+	vibrato: Option<&Vibrato>,
+	tremolo: Option<&Tremolo>,
+	tuning: f64,
+	temperament: &Temperament,
+	sample_rate: u32,
+	portamento_ms: f64,
+	previous_pitch: Option<i32>,
+	bend_semitones: f64,
+	sub_level: f64,
+	unison: Option<&Unison>,
+	arp_rate: Option<f64>,
+	arp_pattern: ArpPattern,
+	grace_debt: f64,
+	humanize: f64,
+	humanize_seed: u64,
+	phase_offset: f64,
+	probability_seed: u64,
+	release_tail: f64,
+	// this track's current `Instruction::Crescendo` multiplier; see
+	// `dynamic_scale`. Only scales `Note`, the only instruction with a real
+	// per-instance velocity of its own
+	dynamics: f64,
+) -> f64 {
+	match instruction {
+		Instruction::Note {pitch, length, velocity, tied, gate, probability, ..} => {
+			if !note_survives(probability_seed, *probability) {
+				return 0.0;
+			}
+
+			let length = (*length * measure_time - delay - grace_debt).max(0.0);
+			let velocity = (*velocity * dynamics * (1.0 + humanize * MAX_HUMANIZE_VELOCITY_FRACTION * humanize_jitter(humanize_seed)))
+				.max(0.0).min(1.0);
+
+			// a nonzero release tail defers this note's own release entirely
+			// to the tail voice `play_track` spawns once the slot ends, so
+			// the slot itself just holds at the sustain level throughout
+			let adsr = if release_tail > 0.0 {&Adsr {release: 0.0, ..*adsr}} else {adsr};
+
+			note_gen(local_t, *pitch, length, waveform, adsr, vibrato, tremolo, velocity, *tied, *gate, tuning, temperament, sample_rate, portamento_ms, previous_pitch, bend_semitones, sub_level, unison, phase_offset)
+		},
+		Instruction::Chord {pitches, length} => {
+			let length = (*length * measure_time - delay - grace_debt).max(0.0);
+
+			match arp_rate {
+				// step through one pitch at a time instead of sustaining the
+				// whole chord; each step is its own little `note_gen` call
+				// with its own local time and envelope, `step_length` long
+				Some(rate) => {
+					let step_length = (rate * measure_time).max(1.0 / f64::from(sample_rate));
+					let step = (local_t / step_length) as usize;
+					let pitch = arp_pitch_at(pitches, arp_pattern, step);
+					let step_t = local_t - step as f64 * step_length;
+
+					note_gen(step_t, pitch, step_length, waveform, adsr, vibrato, tremolo, FULL_VELOCITY, false, 1.0, tuning, temperament, sample_rate, 0.0, None, bend_semitones, sub_level, unison, phase_offset)
+				},
+				None => {
+					let voice_count = pitches.len() as f64;
+
+					pitches.iter()
+						.map(|&pitch| note_gen(local_t, pitch, length, waveform, adsr, vibrato, tremolo, FULL_VELOCITY, false, 1.0, tuning, temperament, sample_rate, 0.0, None, bend_semitones, sub_level, unison, phase_offset))
+						.sum::<f64>() / voice_count
+				},
+			}
+		},
+		Instruction::Slide {from, to, length} => {
+			let length = (*length * measure_time - delay - grace_debt).max(0.0);
+
+			slide_gen(local_t, *from, *to, length, waveform, adsr, tuning, temperament, sample_rate, bend_semitones, sub_level)
+		},
+		Instruction::Rest {..} => 0.0,
+		Instruction::Tempo {..} => unreachable!("tempo changes are skipped before this match"),
+		Instruction::Crescendo {..} => unreachable!("crescendos are skipped before this match, same as tempo changes"),
+		Instruction::Grace {pitch} => {
+			let length = (GRACE_LENGTH * measure_time - delay).max(0.0);
+
+			note_gen(local_t, *pitch, length, waveform, adsr, vibrato, tremolo, FULL_VELOCITY, false, 1.0, tuning, temperament, sample_rate, portamento_ms, previous_pitch, bend_semitones, sub_level, unison, phase_offset)
+		},
+	}
+}
+
+// advances a `DrumTrack` the same way `play_track` advances a `Track`, just
+// without pitch, swing, tuning, or crossfade: drum hits are named sounds
+// with their own fixed envelope, not pitched notes
+fn play_drum_track(t: f64, track: &mut DrumTrack, bpm: f64) -> Option<f64> {
+	let instructions = &track.instructions;
+	let start_of_instruction = &mut track.start_of_instruction;
+	let current_instruction = &mut track.current_instruction;
+
+	let measure_time = 60.0 / bpm * 4.0;
+
+	loop {
+		if *current_instruction >= instructions.len() {
+			return None;
+		}
+
+		let current_length = instructions[*current_instruction].length() * measure_time;
+
+		if t > *start_of_instruction + current_length {
+			*start_of_instruction += current_length;
+			*current_instruction += 1;
+
+			continue;
+		}
+
+		break;
+	}
+
+	let local_t = t - *start_of_instruction;
+
+	Some(match &instructions[*current_instruction] {
+		DrumInstruction::Hit {drum, ..} => drum_gen(local_t, drum),
+		DrumInstruction::Rest {..} => 0.0,
+	})
+}
+
+// synthesizes one of the built-in kit sounds at `t` seconds into the hit.
+// Each drum has its own fixed decay character rather than reading an
+// `Adsr`; a hit's amplitude fades to (near) zero well before any reasonable
+// `length`, so nothing here needs to know how long its slot in the
+// timeline is
+fn drum_gen(t: f64, drum: &Drum) -> f64 {
+	match drum {
+		// a low sine whose pitch drops sharply from the initial punch,
+		// classic of a kick drum's transient
+		Drum::Kick => {
+			let envelope_value = (-t / 0.15).exp();
+			let frequency = 50.0 + 100.0 * (-t / 0.05).exp();
+
+			sin_wave(t * frequency) * envelope_value
+		},
+		// noise for the rattle, blended with a short low tone for body
+		Drum::Snare => {
+			let envelope_value = (-t / 0.1).exp();
+
+			(noise() * 0.6 + sin_wave(t * 190.0) * 0.4) * envelope_value
+		},
+		// pure noise with a very fast decay reads as a closed hi-hat tick
+		Drum::HiHat => {
+			let envelope_value = (-t / 0.04).exp();
+
+			noise() * envelope_value
+		},
+	}
+}
+
+// the pitch a listener would perceive an instruction as "landing on", used
+// as portamento's glide target for the following note. Only notes have an
+// unambiguous single pitch; chords, slides, and rests don't contribute one
+fn instruction_pitch(instruction: &Instruction) -> Option<i32> {
+	match instruction {
+		Instruction::Note {pitch, ..} => Some(*pitch),
+		Instruction::Grace {pitch} => Some(*pitch),
+		_ => None,
+	}
+}
+
+// this instruction's per-note pan override, if it has one; only `Note`
+// carries `pan`, so anything else falls back to `None` and leaves the
+// track's own pan in charge
+fn instruction_pan(instruction: &Instruction) -> Option<f64> {
+	match instruction {
+		Instruction::Note {pan, ..} => *pan,
+		_ => None,
+	}
+}
+
+// the pitch an arpeggiator plays on step `step` of a chord, cycling through
+// `pitches` in `pattern` order forever. An empty chord has nothing to pick,
+// so it falls back to pitch 0 rather than panicking
+fn arp_pitch_at(pitches: &[i32], pattern: ArpPattern, step: usize) -> i32 {
+	let voice_count = pitches.len();
+
+	if voice_count == 0 {
+		return 0;
+	}
+
+	match pattern {
+		ArpPattern::Up => pitches[step % voice_count],
+		ArpPattern::Down => pitches[voice_count - 1 - step % voice_count],
+		ArpPattern::UpDown => {
+			// a full up-down cycle visits every pitch once on the way up and
+			// every pitch but the two ends once on the way down, e.g. a
+			// 4-note chord cycles 1-2-3-4-3-2 (length 6, not 8)
+			let cycle_length = (2 * voice_count - 2).max(1);
+			let position = step % cycle_length;
+
+			if position < voice_count {
+				pitches[position]
+			} else {
+				pitches[cycle_length - position]
+			}
+		},
+	}
+}
+
+fn note_gen(
+	t: f64,
+	pitch: i32,
+	length: f64,
+	waveform: &Waveform,
+	adsr: &Adsr,
+	vibrato: Option<&Vibrato>,
+	tremolo: Option<&Tremolo>,
+	velocity: f64,
+	tied: bool,
+	gate: f64,
+	tuning: f64,
+	temperament: &Temperament,
+	sample_rate: u32,
+	portamento_ms: f64,
+	previous_pitch: Option<i32>,
+	bend_semitones: f64,
+	sub_level: f64,
+	unison: Option<&Unison>,
+	phase_offset: f64,
+) -> f64 {
+	let semitone_offset = vibrato.map_or(0.0, |vibrato| vibrato_offset(t, vibrato)) + bend_semitones;
+	let target_frequency = pitch_compute(pitch, tuning, temperament) * 2.0f64.powf(semitone_offset / 12.0);
+
+	let portamento_seconds = portamento_ms / 1000.0;
+
+	let frequency = match previous_pitch {
+		Some(previous_pitch) if portamento_seconds > 0.0 && t < portamento_seconds => {
+			let previous_frequency = pitch_compute(previous_pitch, tuning, temperament);
+			let progress = (t / portamento_seconds).max(0.0).min(1.0);
+
+			previous_frequency * (target_frequency / previous_frequency).powf(progress)
+		},
+		_ => target_frequency,
+	};
+
+	// real instruments get brighter as well as louder when struck harder;
+	// approximate that by blending a harmonic-free sine (dark) into the
+	// note's actual, harmonic-rich waveform (bright) as velocity rises
+	// `phase_offset` (a fraction of a cycle) turned into a time shift at the
+	// note's own frequency, so two tracks doubling the same pitch don't
+	// sample the waveform in lockstep
+	let phase_offset_t = phase_offset / frequency;
+
+	let bright = unison_wave(t + phase_offset_t, frequency, waveform, sample_rate, unison);
+	let dark = sin_wave(t * frequency);
+	let brightness = velocity.max(0.0).min(1.0);
+	// a sine an octave down, mixed in underneath the note's own waveform, to
+	// add low-end weight a thin waveform (e.g. `Waveform::Square`) can't
+	// provide on its own
+	let sub = sin_wave(t * frequency / 2.0) * sub_level;
+	let raw = dark + (bright - dark) * brightness + sub;
+
+	let tremolo_gain = tremolo.map_or(1.0, |tremolo| {
+		1.0 - tremolo.depth * (0.5 - 0.5 * (TAU * tremolo.rate_hz * t).cos())
+	});
+
+	// the envelope runs over the voiced portion of the note (`length * gate`)
+	// and is silent for the rest, independent of the ADSR shape itself
+	let gated_length = length * gate;
+
+	let envelope_value = if gated_length <= 0.0 {
+		0.0
+	} else if tied {
+		envelope_tied(t / gated_length, adsr)
+	} else {
+		envelope(t / gated_length, adsr)
+	};
+
+	raw * envelope_value * 0.96f64.powi(pitch) * velocity * tremolo_gain
+}
+
+// renders `waveform` at `frequency`, or, with `unison` attached, sums
+// `unison.voices` copies detuned symmetrically across `unison.detune_cents`
+// and averages them back to unity gain. A single voice (or no `unison` at
+// all) is the same as calling `waveform_raw` directly
+fn unison_wave(t: f64, frequency: f64, waveform: &Waveform, sample_rate: u32, unison: Option<&Unison>) -> f64 {
+	let unison = match unison {
+		Some(unison) if unison.voices > 1 => unison,
+		_ => return waveform_raw(t, frequency, waveform, sample_rate),
+	};
+
+	let voice_count = unison.voices;
+
+	(0 .. voice_count)
+		.map(|voice| {
+			let spread = voice as f64 / (voice_count - 1) as f64 - 0.5;
+			let detuned_frequency = frequency * 2.0f64.powf(spread * unison.detune_cents / 1200.0);
+
+			waveform_raw(t, detuned_frequency, waveform, sample_rate)
+		})
+		.sum::<f64>() / f64::from(voice_count)
+}
+
+// slides the effective pitch from `from` to `to` linearly, in log-frequency
+// space, over the note's duration
+fn slide_gen(
+	t: f64,
+	from: i32,
+	to: i32,
+	length: f64,
+	waveform: &Waveform,
+	adsr: &Adsr,
+	tuning: f64,
+	temperament: &Temperament,
+	sample_rate: u32,
+	bend_semitones: f64,
+	sub_level: f64,
+) -> f64 {
+	let progress = (t / length).max(0.0).min(1.0);
+
+	let start_frequency = pitch_compute(from, tuning, temperament);
+	let end_frequency = pitch_compute(to, tuning, temperament);
+	let frequency = start_frequency * (end_frequency / start_frequency).powf(progress) * 2.0f64.powf(bend_semitones / 12.0);
+
+	let sub = sin_wave(t * frequency / 2.0) * sub_level;
+	let raw = waveform_raw(t, frequency, waveform, sample_rate) + sub;
+	let average_pitch = from + to;
+
+	raw * envelope(t / length, adsr) * 0.96f64.powi(average_pitch / 2)
+}
+
+// renders one waveform's raw (pre-envelope) sample at time `t` for a note at
+// the given instantaneous `frequency`
+fn waveform_raw(t: f64, frequency: f64, waveform: &Waveform, sample_rate: u32) -> f64 {
+	let x = t * frequency;
+
+	match waveform {
+		Waveform::Sin => table_lookup(sine_table(), x),
+		Waveform::Sawtooth => table_lookup(sawtooth_table(), x),
+		Waveform::SawtoothAntiAliased => {
+			let dt = frequency / f64::from(sample_rate);
+
+			sawtooth_polyblep(x, dt)
+		},
+		Waveform::Square => table_lookup(square_table(), x),
+		Waveform::Triangle => triangle_wave(x),
+		Waveform::SquareAntiAliased => square_additive(x, frequency, sample_rate),
+		Waveform::TriangleAntiAliased => triangle_additive(x, frequency, sample_rate),
+		Waveform::Pulse {duty} => pulse_wave(x, *duty),
+		Waveform::Fm {ratio, index} => fm_wave(t, frequency, *ratio, *index),
+		Waveform::Sync {slave_ratio} => sync_wave(t, frequency, *slave_ratio),
+		Waveform::Noise => noise(),
+	}
+}
+
+// sine, sawtooth, and square are the oscillators hot enough (used by every
+// undecorated note, and by `SawtoothAntiAliased`'s cheaper cousin) to be
+// worth trading a per-sample `.sin()` call or branchy comparison for a
+// table lookup. Each table holds one full cycle, generated once by sampling
+// straight from the existing analytic function (`sin_wave`/`sawtooth`/
+// `square_wave`), so it's exact to within interpolation error by
+// construction rather than something a separate test needs to check.
+// `std::sync::OnceLock` gives the same "compute once, share globally" shape
+// as `once_cell`/`lazy_static` without pulling in a dependency for
+// something the standard library now provides directly
+const WAVETABLE_SIZE: usize = 2048;
+
+fn build_wavetable(f: impl Fn(f64) -> f64) -> [f64; WAVETABLE_SIZE] {
+	let mut table = [0.0; WAVETABLE_SIZE];
+
+	for (i, sample) in table.iter_mut().enumerate() {
+		*sample = f(i as f64 / WAVETABLE_SIZE as f64);
+	}
+
+	table
+}
+
+fn sine_table() -> &'static [f64; WAVETABLE_SIZE] {
+	static TABLE: std::sync::OnceLock<[f64; WAVETABLE_SIZE]> = std::sync::OnceLock::new();
+
+	TABLE.get_or_init(|| build_wavetable(sin_wave))
+}
+
+fn sawtooth_table() -> &'static [f64; WAVETABLE_SIZE] {
+	static TABLE: std::sync::OnceLock<[f64; WAVETABLE_SIZE]> = std::sync::OnceLock::new();
+
+	TABLE.get_or_init(|| build_wavetable(sawtooth))
+}
+
+fn square_table() -> &'static [f64; WAVETABLE_SIZE] {
+	static TABLE: std::sync::OnceLock<[f64; WAVETABLE_SIZE]> = std::sync::OnceLock::new();
+
+	TABLE.get_or_init(|| build_wavetable(square_wave))
+}
+
+// linearly interpolates one cycle's worth of `table` at `phase` (in cycles,
+// any sign or magnitude; wrapped down to 0.0..1.0 first)
+fn table_lookup(table: &[f64; WAVETABLE_SIZE], phase: f64) -> f64 {
+	let position = phase.rem_euclid(1.0) * WAVETABLE_SIZE as f64;
+	let index = position as usize;
+	let next_index = (index + 1) % WAVETABLE_SIZE;
+	let fraction = position - index as f64;
+
+	table[index] * (1.0 - fraction) + table[next_index] * fraction
+}
+
+// a xorshift64 generator seeded with a fixed constant, so a given source
+// renders identically from run to run. It lives in a thread-local rather
+// than being threaded through every waveform's call chain, since noise is
+// the only waveform that needs state to persist across samples.
+thread_local! {
+	static NOISE_STATE: std::cell::Cell<u64> = std::cell::Cell::new(0x2545_f491_4f6c_dd1d);
+}
+
+fn noise() -> f64 {
+	NOISE_STATE.with(|state| {
+		let mut x = state.get();
+
+		x ^= x << 13;
+		x ^= x >> 7;
+		x ^= x << 17;
+
+		state.set(x);
+
+		(x >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+	})
+}
+
+// bounds how far `humanize: 1.0` can push a note, as a fraction of its own
+// nominal length (timing) or its written velocity (velocity). Both are kept
+// well under half a note's length so two adjacent notes can never swap order
+const MAX_HUMANIZE_TIMING_FRACTION: f64 = 0.1;
+const MAX_HUMANIZE_VELOCITY_FRACTION: f64 = 0.2;
+
+// hashes `seed` down to a value in -1.0..=1.0, used to jitter a single
+// note's timing or velocity when `humanize` is enabled. Unlike `noise`, this
+// is a pure function of its seed rather than a persistent generator: seeking
+// or re-rendering the same instruction always reproduces the same jitter,
+// and different seeds (e.g. an instruction index and its salted twin) never
+// interfere with each other's state
+fn humanize_jitter(seed: u64) -> f64 {
+	let mut x = seed.wrapping_mul(0x2545_f491_4f6c_dd1d).wrapping_add(1);
+
+	x ^= x << 13;
+	x ^= x >> 7;
+	x ^= x << 17;
+
+	(x >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+// hashes `seed` down to a value in 0.0..1.0 and compares it against
+// `probability`, deciding whether an `Instruction::Note` sounds on a given
+// pass. Pure like `humanize_jitter`, for the same reason: seeking or
+// re-rendering the same instruction on the same pass always reproduces the
+// same decision, rather than drifting with however many draws happened to
+// come before it
+fn note_survives(seed: u64, probability: f64) -> bool {
+	let mut x = seed.wrapping_mul(0x2545_f491_4f6c_dd1d).wrapping_add(1);
+
+	x ^= x << 13;
+	x ^= x >> 7;
+	x ^= x << 17;
+
+	let roll = (x >> 11) as f64 / (1u64 << 53) as f64;
+
+	roll < probability
+}
+
+// combines a track's own RNG seed, its current loop pass, and an
+// instruction's index into a single seed for `note_survives`, so the same
+// instruction gets a fresh, independent draw each time playback loops back
+// around to it, while still reproducing identically if that same pass is
+// seeked into or re-rendered
+fn note_probability_seed(track_seed: u64, pass: u64, index: usize) -> u64 {
+	track_seed
+		^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+		^ pass.wrapping_mul(0xD1B5_4A32_D192_ED03)
+}
+
+// phase-modulates a sine carrier at `frequency` with a sine modulator running
+// at `frequency * ratio`, deviating the carrier's phase by up to `index` radians
+fn fm_wave(t: f64, frequency: f64, ratio: f64, index: f64) -> f64 {
+	let modulator = (t * frequency * ratio * TAU).sin();
+
+	(t * frequency * TAU + index * modulator).sin()
+}
+
+// the master's phase (in 0.0..1.0, wrapping once per note cycle) drives when
+// the slave resets; the slave's own phase is however much of its faster
+// cycle it's completed since that last reset. Both are pure functions of
+// `t`, so no phase register needs to persist across samples the way it
+// would in an oscillator implemented by incremental phase accumulation
+fn sync_wave(t: f64, frequency: f64, slave_ratio: f64) -> f64 {
+	let master_phase = (t * frequency).rem_euclid(1.0);
+	let slave_phase = (master_phase * slave_ratio).rem_euclid(1.0);
+
+	sawtooth(slave_phase)
+}
+
+// a pure sine of `x` cycles (not radians), e.g. `sin_wave(t * frequency)` for
+// a tone at `frequency` Hz at time `t`. Exposed publicly since it's also
+// useful on its own for calibration tones/tests that don't need a whole
+// `Source` built around them
+pub fn sin_wave(x: f64) -> f64 {
+	(x * TAU).sin()
+}
+
+fn square_wave(mut x: f64) -> f64 {
+	x %= 1.0;
+
+	if x < 0.5 {
+		1.0
+	} else {
+		-1.0
+	}
+}
+
+// ramps linearly from -1 to 1 over the first half period and back down over
+// the second half; mellower than the sawtooth, richer than the sine
+fn triangle_wave(mut x: f64) -> f64 {
+	x %= 1.0;
+
+	if x < 0.5 {
+		4.0 * x - 1.0
+	} else {
+		3.0 - 4.0 * x
+	}
+}
+
+// `duty` in 0.0..=1.0 controls the fraction of the period spent at +1; the
+// extremes (0.0, 1.0) degenerate to a constant -1 or +1 rather than panicking
+fn pulse_wave(mut x: f64, duty: f64) -> f64 {
+	x %= 1.0;
+
+	if x < duty.max(0.0).min(1.0) {
+		1.0
+	} else {
+		-1.0
+	}
+}
+
+fn sawtooth(mut x: f64) -> f64 {
+	// `%` keeps the sign of the input, so a negative `x` (negative pitches,
+	// or phase fed in by vibrato/slide) would fall through every branch
+	// below and hit the panic. `rem_euclid` always lands in [0, 1).
+	x = x.rem_euclid(1.0);
+
+	if 0.0 <= x && x < 0.25 {
+		return x * 4.0;
+	}
+
+	if 0.25 <= x && x < 0.75 {
+		return 2.0 - x * 4.0;
+	}
+
+	if 0.75 <= x && x < 1.0 {
+		return x * 4.0 - 4.0;
+	}
+
+	unreachable!("rem_euclid should always produce a value in [0, 1)")
+}
+
+// PolyBLEP-corrected sawtooth: a rising ramp from -1 to 1 whose reset at the
+// end of each cycle is smoothed, so it aliases far less on high notes than
+// an uncorrected ramp would (`sawtooth` above is a continuous piecewise-
+// linear shape with no such reset). `dt` is the phase increment per sample
+// (frequency / sample_rate).
+fn sawtooth_polyblep(x: f64, dt: f64) -> f64 {
+	let t = x.rem_euclid(1.0);
+
+	2.0 * t - 1.0 - poly_blep(t, dt)
+}
+
+// how many harmonics of `frequency` fit under `sample_rate`'s Nyquist limit,
+// rounded down so the top harmonic never aliases
+fn max_harmonic(frequency: f64, sample_rate: u32) -> i32 {
+	let nyquist = f64::from(sample_rate) / 2.0;
+
+	(nyquist / frequency).floor().max(1.0) as i32
+}
+
+// band-limited square wave: a square only has odd harmonics, each at 1/n the
+// fundamental's amplitude, so summing however many fit under Nyquist gives
+// the naive `Square`'s shape without the aliasing its hard edges cause
+fn square_additive(x: f64, frequency: f64, sample_rate: u32) -> f64 {
+	let harmonics = max_harmonic(frequency, sample_rate);
+	let mut sum = 0.0;
+	let mut n = 1;
+
+	while n <= harmonics {
+		sum += (n as f64 * x * TAU).sin() / n as f64;
+		n += 2;
+	}
+
+	sum * 4.0 / PI
+}
+
+// band-limited triangle wave: like `square_additive`, but each odd harmonic
+// falls off as 1/n^2 and alternates sign, which is what rounds the square's
+// corners into the triangle's smoother slopes
+fn triangle_additive(x: f64, frequency: f64, sample_rate: u32) -> f64 {
+	let harmonics = max_harmonic(frequency, sample_rate);
+	let mut sum = 0.0;
+	let mut n = 1;
+	let mut sign = 1.0;
+
+	while n <= harmonics {
+		sum += sign * (n as f64 * x * TAU).sin() / (n * n) as f64;
+		n += 2;
+		sign = -sign;
+	}
+
+	sum * 8.0 / (PI * PI)
+}
+
+fn poly_blep(t: f64, dt: f64) -> f64 {
+	if t < dt {
+		let t = t / dt;
+
+		t + t - t * t - 1.0
+	} else if t > 1.0 - dt {
+		let t = (t - 1.0) / dt;
+
+		t * t + t + t + 1.0
+	} else {
+		0.0
+	}
+}
+
+// how far from A4 (in semitones, either direction) `equal_temperament_ratio`'s
+// cache covers; pitches outside this range are just computed directly, which
+// is exactly what building a wider table would have done anyway
+const PITCH_CACHE_RANGE: i32 = 48;
+
+// `2^(pitch/12)`, i.e. the equal-temperament frequency ratio to `tuning`, for
+// every integer pitch in -PITCH_CACHE_RANGE..=PITCH_CACHE_RANGE. Built once
+// on first use. Only equal temperament gets a cache: just intonation's ratio
+// also depends on `tonic`, which varies per `Source`, so there's no single
+// table that would stay valid across all of them
+fn equal_temperament_ratio(pitch: i32) -> f64 {
+	if pitch < -PITCH_CACHE_RANGE || pitch > PITCH_CACHE_RANGE {
+		return 2.0f64.powf(1.0 / 12.0).powi(pitch);
+	}
+
+	static CACHE: OnceLock<Vec<f64>> = OnceLock::new();
+
+	let table = CACHE.get_or_init(|| {
+		(-PITCH_CACHE_RANGE ..= PITCH_CACHE_RANGE)
+			.map(|pitch| 2.0f64.powf(1.0 / 12.0).powi(pitch))
+			.collect()
+	});
+
+	table[(pitch + PITCH_CACHE_RANGE) as usize]
+}
+
+// `tuning` is the frequency, in Hz, that pitch offset 0 (A4) resolves to
+fn pitch_compute(pitch: i32, tuning: f64, temperament: &Temperament) -> f64 {
+	match temperament {
+		Temperament::EqualTemperament => tuning * equal_temperament_ratio(pitch),
+		Temperament::JustIntonation {tonic} => {
+			let offset = pitch - tonic;
+			let octave = offset.div_euclid(12);
+			let semitone = offset.rem_euclid(12) as usize;
+
+			let tonic_frequency = tuning * 2.0f64.powf(1.0 / 12.0).powi(*tonic);
+
+			tonic_frequency * JUST_INTONATION_RATIOS[semitone] * 2.0f64.powi(octave)
+		},
+	}
+}
+
+// the pitch shift, in semitones, contributed by vibrato at time `t` into the
+// note. Ramps in linearly over the `delay` .. 2 * delay window so quick notes
+// don't wobble.
+fn vibrato_offset(t: f64, vibrato: &Vibrato) -> f64 {
+	let ramp = ((t - vibrato.delay) / vibrato.delay.max(1e-9)).max(0.0).min(1.0);
+
+	ramp * vibrato.depth_semitones * (t * vibrato.rate_hz * TAU).sin()
+}
+
+fn envelope(x: f64, adsr: &Adsr) -> f64 {
+	if x < 0.0 || x > 1.0 {
+		return 0.0;
+	}
+
+	let attack_end = adsr.attack;
+	let decay_end = attack_end + adsr.decay;
+	let release_start = 1.0 - adsr.release;
+
+	if x < attack_end {
+		let attack_frac = if attack_end > 0.0 {x / attack_end} else {1.0};
+
+		return attack_frac.powf(adsr.curve);
+	}
+
+	if x < decay_end {
+		let decay_frac = if adsr.decay > 0.0 {(x - attack_end) / adsr.decay} else {1.0};
+
+		return 1.0 - decay_frac * (1.0 - adsr.sustain);
+	}
+
+	if x < release_start {
+		return adsr.sustain;
+	}
+
+	if adsr.release > 0.0 {
+		let release_frac = (x - release_start) / adsr.release;
+
+		return adsr.sustain * (1.0 - release_frac.powf(adsr.curve));
+	}
+
+	adsr.sustain
+}
+
+// like `envelope`, but for a tied note: skips straight to the sustain level
+// instead of re-running the attack/decay ramp, so a tied run of notes reads
+// as one continuous tone rather than re-articulating on every instruction.
+// The release stage still runs normally at the end of the note
+fn envelope_tied(x: f64, adsr: &Adsr) -> f64 {
+	if x < 0.0 || x > 1.0 {
+		return 0.0;
+	}
+
+	let release_start = 1.0 - adsr.release;
+
+	if x < release_start {
+		return adsr.sustain;
+	}
+
+	if adsr.release > 0.0 {
+		let release_frac = (x - release_start) / adsr.release;
+
+		return adsr.sustain * (1.0 - release_frac.powf(adsr.curve));
+	}
+
+	adsr.sustain
+}
+
+pub const WHOLE: f64 = 1.0;
+pub const HALF: f64 = 1.0 / 2.0;
+pub const QUARTER: f64 = 1.0 / 4.0;
+pub const N8TH: f64 = 1.0 / 8.0;
+pub const N16TH: f64 = 1.0 / 16.0;
+pub const N32ND: f64 = 1.0 / 32.0;
+
+// a dotted note lasts one and a half times as long as the plain note
+pub fn dotted(length: f64) -> f64 {
+	length * 1.5
+}
+
+// a double-dotted note lasts one and three-quarters times as long
+pub fn double_dotted(length: f64) -> f64 {
+	length * 1.75
+}
+
+// a triplet fits three notes into the time two of the same base value would
+// normally take, so each one lasts two-thirds as long. `triplet(N8TH)` is
+// the usual eighth-note triplet (1/12); `triplet(N16TH)` a sixteenth-note
+// triplet (1/24)
+pub fn triplet(length: f64) -> f64 {
+	length * 2.0 / 3.0
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+	pub line: usize,
+	pub column: usize,
+	pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "parse error at line {}, column {}: {}", self.line, self.column, self.message)
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+// maps each base letter to a semitone adjustment (sharps positive, flats
+// negative) that's applied to notes of that letter unless the token carries
+// its own explicit accidental. `KeySignature::natural()` (C major / A minor)
+// leaves every letter alone
+pub struct KeySignature {
+	accidentals: [i32; 7],
+}
+
+// the order sharps and flats are added in a real key signature, going by
+// base letter
+const SHARP_ORDER: [char; 7] = ['F', 'C', 'G', 'D', 'A', 'E', 'B'];
+const FLAT_ORDER: [char; 7] = ['B', 'E', 'A', 'D', 'G', 'C', 'F'];
+
+// (tonic, mode, sharps if positive / flats if negative) for every major and
+// natural minor key signature, ordered around the circle of fifths
+const KEY_TABLE: &[(&str, &str, i32)] = &[
+	("C", "major", 0), ("A", "minor", 0),
+	("G", "major", 1), ("E", "minor", 1),
+	("D", "major", 2), ("B", "minor", 2),
+	("A", "major", 3), ("F#", "minor", 3),
+	("E", "major", 4), ("C#", "minor", 4),
+	("B", "major", 5), ("G#", "minor", 5),
+	("F#", "major", 6), ("D#", "minor", 6),
+	("C#", "major", 7), ("A#", "minor", 7),
+	("F", "major", -1), ("D", "minor", -1),
+	("Bb", "major", -2), ("G", "minor", -2),
+	("Eb", "major", -3), ("C", "minor", -3),
+	("Ab", "major", -4), ("F", "minor", -4),
+	("Db", "major", -5), ("Bb", "minor", -5),
+	("Gb", "major", -6), ("Eb", "minor", -6),
+	("Cb", "major", -7), ("Ab", "minor", -7),
+];
+
+impl KeySignature {
+	// no sharps or flats: C major / A minor
+	pub fn natural() -> Self {
+		KeySignature {accidentals: [0; 7]}
+	}
+
+	// looks `tonic` (e.g. "D", "F#", "Bb") and `mode` ("major" or "minor")
+	// up in the standard table of key signatures
+	pub fn new(tonic: &str, mode: &str) -> Option<Self> {
+		let count = KEY_TABLE.iter()
+			.find(|(key_tonic, key_mode, _)| *key_tonic == tonic && *key_mode == mode)
+			.map(|(_, _, count)| *count)?;
+
+		let mut accidentals = [0; 7];
+		let order = if count >= 0 {SHARP_ORDER} else {FLAT_ORDER};
+		let adjustment = if count >= 0 {1} else {-1};
+
+		for letter in order.iter().take(count.abs() as usize) {
+			accidentals[letter_index(*letter)] += adjustment;
+		}
+
+		Some(KeySignature {accidentals})
+	}
+
+	fn accidental_for(&self, letter: char) -> i32 {
+		self.accidentals[letter_index(letter)]
+	}
+}
+
+fn letter_index(letter: char) -> usize {
+	match letter.to_ascii_uppercase() {
+		'C' => 0,
+		'D' => 1,
+		'E' => 2,
+		'F' => 3,
+		'G' => 4,
+		'A' => 5,
+		'B' => 6,
+		_ => unreachable!("letter_index is only ever called with a validated note letter"),
+	}
+}
+
+// parses a `key: <tonic> <mode>` line, e.g. `key: D major`
+fn parse_key_line(rest: &str, line: usize) -> Result<KeySignature, ParseError> {
+	let mut tokens = rest.split_whitespace();
+
+	let tonic = tokens.next().ok_or_else(|| ParseError {
+		line,
+		column: 5,
+		message: "expected a tonic note name after `key:`".to_string(),
+	})?;
+
+	let mode = tokens.next().ok_or_else(|| ParseError {
+		line,
+		column: 5 + tonic.len() + 1,
+		message: "expected `major` or `minor` after the tonic".to_string(),
+	})?;
+
+	KeySignature::new(tonic, mode).ok_or_else(|| ParseError {
+		line,
+		column: 5,
+		message: format!("unknown key signature `{} {}`", tonic, mode),
+	})
+}
+
+// song-level settings optionally declared in a text notation file's header,
+// before any note lines. `parse_track` only builds a single `Track`, so it
+// hands these back separately rather than applying them itself; a caller
+// assembling a full `Source` (see `--track` in `main.rs`) decides whether
+// to apply them, e.g. via `with_tuning`/`with_time_signature`. `key:` isn't
+// included here since it's already fully applied by the time `parse_track`
+// returns: it only ever affects how note letters in this same file resolve
+// to pitches
+#[derive(Default)]
+pub struct SongMeta {
+	pub title: Option<String>,
+	pub bpm: Option<f64>,
+	pub time_signature: Option<TimeSignature>,
+	pub tuning: Option<f64>,
+}
+
+// parses line-based notation such as `C4 q`, `R e`, `G#3 h` into a Track,
+// using a default sawtooth waveform and default ADSR envelope. An optional
+// header block of `title:`, `bpm:`, `time:`, `key:`, `tuning:` lines may
+// come first; `key: <tonic> <mode>` (e.g. `key: D major`) sharps or flats
+// note letters per the signature, while the rest are reported back via the
+// returned `SongMeta` for the caller to apply. An explicit `#`/`b` on a
+// note overrides the key regardless. `//` starts a line comment, and a
+// `|: ... :|` block repeats its lines (`:|` alone repeats twice, `:|x3`
+// repeats three times); see `expand_repeats` for the repeat syntax itself
+pub fn parse_track(input: &str) -> Result<(Track, SongMeta), ParseError> {
+	let mut instructions = Vec::new();
+	let mut key = KeySignature::natural();
+	let mut meta = SongMeta::default();
+
+	for (line_number, line) in expand_repeats(input)? {
+		let line = line.trim();
+
+		if line.is_empty() {
+			continue;
+		}
+
+		if line.starts_with("key:") {
+			key = parse_key_line(line[4 ..].trim(), line_number)?;
+
+			continue;
+		}
+
+		if line.starts_with("title:") {
+			meta.title = Some(line[6 ..].trim().to_string());
+
+			continue;
+		}
+
+		if line.starts_with("bpm:") {
+			let value = line[4 ..].trim();
+
+			meta.bpm = Some(value.parse().map_err(|_| ParseError {
+				line: line_number,
+				column: 5,
+				message: format!("expected a floating point tempo after `bpm:`, found `{}`", value),
+			})?);
+
+			continue;
+		}
+
+		if line.starts_with("time:") {
+			meta.time_signature = Some(parse_time_signature_line(line[5 ..].trim(), line_number)?);
+
+			continue;
+		}
+
+		if line.starts_with("tuning:") {
+			let value = line[7 ..].trim();
+
+			meta.tuning = Some(value.parse().map_err(|_| ParseError {
+				line: line_number,
+				column: 8,
+				message: format!("expected a floating point tuning reference after `tuning:`, found `{}`", value),
+			})?);
+
+			continue;
+		}
+
+		// any other `word:` first token reads as an unrecognized header
+		// key rather than a note token; warn and skip the line instead of
+		// failing the whole file over, say, a typo'd `titel:`
+		if let Some(first_token) = line.split_whitespace().next() {
+			if let Some(key_name) = first_token.strip_suffix(':') {
+				eprintln!("warning: line {}: unknown header key `{}:`, ignoring", line_number, key_name);
+
+				continue;
+			}
+		}
+
+		let mut tokens = line.split_whitespace();
+
+		let note_token = tokens.next().ok_or_else(|| ParseError {
+			line: line_number,
+			column: 1,
+			message: "expected a note or rest token".to_string(),
+		})?;
+
+		let duration_token = tokens.next().ok_or_else(|| ParseError {
+			line: line_number,
+			column: note_token.len() + 2,
+			message: "expected a duration code".to_string(),
+		})?;
+
+		let length = parse_duration(duration_token, line_number, note_token.len() + 2)?;
+
+		let instruction = if note_token.eq_ignore_ascii_case("r") {
+			Instruction::Rest {length}
+		} else {
+			let pitch = parse_pitch(note_token, &key, line_number, 1)?;
+
+			Instruction::Note {pitch, length, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None}
+		};
+
+		instructions.push(instruction);
+	}
+
+	let track = Track::new(instructions, Waveform::Sawtooth, Adsr::default(), 0.0);
+
+	Ok((track, meta))
+}
+
+// parses a `time: <n>/<d>` line, e.g. `time: 3/4`
+fn parse_time_signature_line(value: &str, line: usize) -> Result<TimeSignature, ParseError> {
+	let mut parts = value.splitn(2, '/');
+
+	let numerator = parts.next().filter(|s| !s.is_empty()).ok_or_else(|| ParseError {
+		line,
+		column: 6,
+		message: "expected a numerator after `time:`".to_string(),
+	})?;
+
+	let denominator = parts.next().filter(|s| !s.is_empty()).ok_or_else(|| ParseError {
+		line,
+		column: 6 + numerator.len() + 1,
+		message: "expected `<numerator>/<denominator>` after `time:`".to_string(),
+	})?;
+
+	let denominator_column = 6 + numerator.len() + 1;
+
+	let numerator = numerator.parse().map_err(|_| ParseError {
+		line,
+		column: 6,
+		message: format!("expected an integer numerator, found `{}`", numerator),
+	})?;
+
+	let denominator = denominator.parse().map_err(|_| ParseError {
+		line,
+		column: denominator_column,
+		message: format!("expected an integer denominator, found `{}`", denominator),
+	})?;
+
+	Ok(TimeSignature {numerator, denominator})
+}
+
+// strips `//` line comments and expands `|: ... :|` repeat blocks, returning
+// the surviving non-blank lines paired with their original (1-based) line
+// number for error reporting. A bare `:|` repeats its block twice in total;
+// `:|x3` repeats it 3 times. Nesting a `|:` inside another open block is a
+// parse error, as is a `:|` with no matching `|:` or a `|:` left unclosed
+fn expand_repeats(input: &str) -> Result<Vec<(usize, String)>, ParseError> {
+	let mut expanded = Vec::new();
+	let mut open_repeat: Option<(usize, Vec<(usize, String)>)> = None;
+	let mut last_line = 0;
+
+	for (i, raw_line) in input.lines().enumerate() {
+		let line_number = i + 1;
+		last_line = line_number;
+
+		let line = match raw_line.find("//") {
+			Some(pos) => &raw_line[.. pos],
+			None => raw_line,
+		}.trim();
+
+		if line.is_empty() {
+			continue;
+		}
+
+		if line == "|:" {
+			if let Some((opened_at, _)) = open_repeat {
+				return Err(ParseError {
+					line: line_number,
+					column: 1,
+					message: format!("nested repeats aren't supported; already inside the `|:` opened at line {}", opened_at),
+				});
+			}
+
+			open_repeat = Some((line_number, Vec::new()));
+
+			continue;
+		}
+
+		if line.starts_with(":|") {
+			let (_, block) = open_repeat.take().ok_or_else(|| ParseError {
+				line: line_number,
+				column: 1,
+				message: "found `:|` with no matching `|:`".to_string(),
+			})?;
+
+			let count = parse_repeat_count(line[2 ..].trim(), line_number)?;
+
+			for _ in 0 .. count {
+				expanded.extend(block.iter().cloned());
+			}
+
+			continue;
+		}
+
+		match &mut open_repeat {
+			Some((_, block)) => block.push((line_number, line.to_string())),
+			None => expanded.push((line_number, line.to_string())),
+		}
+	}
+
+	if let Some((opened_at, _)) = open_repeat {
+		return Err(ParseError {
+			line: last_line,
+			column: 1,
+			message: format!("unterminated `|:` opened at line {}", opened_at),
+		});
+	}
+
+	Ok(expanded)
+}
+
+// parses the count suffix on a repeat's closing `:|`: empty means "repeat
+// twice", `xN` means "repeat N times"
+fn parse_repeat_count(suffix: &str, line: usize) -> Result<u32, ParseError> {
+	if suffix.is_empty() {
+		return Ok(2);
+	}
+
+	let digits = suffix.strip_prefix('x').ok_or_else(|| ParseError {
+		line,
+		column: 1,
+		message: format!("expected `:|` or `:|xN`, found `:|{}`", suffix),
+	})?;
+
+	digits.parse().map_err(|_| ParseError {
+		line,
+		column: 1,
+		message: format!("invalid repeat count `x{}`", digits),
+	})
+}
+
+// parses a duration code like `q`, `q.` (dotted), `q..` (double-dotted), or
+// `qt` (triplet; combines with dots, e.g. `q.t`)
+fn parse_duration(token: &str, line: usize, column: usize) -> Result<f64, ParseError> {
+	let (token, is_triplet) = match token.strip_suffix('t') {
+		Some(stripped) => (stripped, true),
+		None => (token, false),
+	};
+
+	let dots = token.len() - token.trim_end_matches('.').len();
+	let base = &token[.. token.len() - dots];
+
+	let length = match base {
+		"w" => WHOLE,
+		"h" => HALF,
+		"q" => QUARTER,
+		"e" => N8TH,
+		"s" => N16TH,
+		_ => return Err(ParseError {
+			line,
+			column,
+			message: format!("unknown duration code `{}`", token),
+		}),
+	};
+
+	let length = match dots {
+		0 => length,
+		1 => dotted(length),
+		2 => double_dotted(length),
+		_ => return Err(ParseError {
+			line,
+			column,
+			message: format!("too many dots in duration code `{}`", token),
+		}),
+	};
+
+	Ok(if is_triplet {triplet(length)} else {length})
+}
+
+// converts a note name like `C4` or `G#3` into a pitch offset relative to A4
+fn parse_pitch(token: &str, key: &KeySignature, line: usize, column: usize) -> Result<i32, ParseError> {
+	let mut chars = token.chars().peekable();
+
+	let letter = chars.next().ok_or_else(|| ParseError {
+		line,
+		column,
+		message: "expected a note letter".to_string(),
+	})?;
+
+	let base = match letter.to_ascii_uppercase() {
+		'C' => 0,
+		'D' => 2,
+		'E' => 4,
+		'F' => 5,
+		'G' => 7,
+		'A' => 9,
+		'B' => 11,
+		_ => return Err(ParseError {
+			line,
+			column,
+			message: format!("unknown note letter `{}`", letter),
+		}),
+	};
+
+	let mut semitone = base;
+
+	match chars.peek() {
+		Some('#') => {
+			semitone += 1;
+			chars.next();
+		},
+		Some('b') => {
+			semitone -= 1;
+			chars.next();
+		},
+		// no explicit accidental on this note, so the key signature applies
+		_ => semitone += key.accidental_for(letter),
+	}
+
+	let octave_str: String = chars.collect();
+
+	let octave: i32 = octave_str.parse().map_err(|_| ParseError {
+		line,
+		column: column + token.len() - octave_str.len(),
+		message: format!("invalid octave `{}`", octave_str),
+	})?;
+
+	Ok(12 * (octave - 4) + (semitone - 9))
+}
+
+// converts a note name like "A4", "C#3", or "Eb5" into the same pitch offset
+// from A4 that `Instruction::Note`'s `pitch` field wants, without needing a
+// whole track's worth of text notation around it. Uses the same grammar
+// `parse_track` does; since there's no key signature to resolve a bare
+// letter against here, one with no explicit accidental is read against
+// `KeySignature::natural()` (i.e. exactly as written, no implicit sharps or
+// flats)
+pub fn pitch_of(name: &str) -> Result<i32, ParseError> {
+	parse_pitch(name, &KeySignature::natural(), 1, 1)
+}
+
+// renders `source` offline to a 16-bit PCM WAV file, terminating exactly when
+// `play_source` would end the live streaming path. `channels` must be 1 or 2:
+// 1 sums each frame's left/right down to mono the same way `Source::samples`
+// does, 2 writes them out as an interleaved stereo file honoring per-track
+// pan. Under `PanLaw::Linear`, a center-panned note ends up bit-identical in
+// both channels, since that pan law splits amplitude by an exact 0.5/0.5 at
+// pan 0.0; the default `PanLaw::ConstantPower` instead scales by `cos`/`sin`
+// of the pan angle, which only agree at pan 0.0 up to floating-point
+// rounding, not bit-for-bit
+pub fn render_to_wav(source: &mut Source, path: &str, sample_rate: u32, channels: u16) -> std::io::Result<()> {
+	assert!(channels == 1 || channels == 2, "render_to_wav only supports 1 (mono) or 2 (stereo) channels, got {}", channels);
+
+	let mut interleaved = Vec::new();
+
+	if channels == 2 {
+		for (left, right) in source.stereo_samples(sample_rate) {
+			interleaved.push(left);
+			interleaved.push(right);
+		}
+	} else {
+		interleaved.extend(source.samples(sample_rate));
+	}
+
+	write_wav(path, sample_rate, channels, &interleaved)
+}
+
+// renders `source` offline (same code path as `render_to_wav`) and streams
+// the result to `writer` as headerless raw PCM: mono, 16-bit signed
+// little-endian samples at `sample_rate`, i.e. the same bytes `write_wav`
+// puts in a WAV's `data` chunk, minus the RIFF/fmt framing. Meant for piping
+// straight into another tool (`sox -t raw -r 44100 -e signed -b 16 -c 1 ...`,
+// `ffmpeg -f s16le -ar 44100 -ac 1 ...`) without an intermediate file
+pub fn render_to_raw<W: Write>(source: &mut Source, writer: &mut W, sample_rate: u32) -> std::io::Result<()> {
+	for sample in source.samples(sample_rate) {
+		let clamped = sample.max(-1.0).min(1.0);
+		let value = (clamped * f64::from(i16::MAX)) as i16;
+
+		writer.write_all(&value.to_le_bytes())?;
+	}
+
+	Ok(())
+}
+
+// window/hop sizes for `render_to_spectrogram`'s STFT. `WINDOW_SIZE` is a
+// power of two, as required by the radix-2 `fft` below; `HOP_SIZE` is a
+// quarter of it, a conventional 75% overlap that keeps the time axis smooth
+// without quadrupling the FFT count the way a smaller hop would
+const SPECTROGRAM_WINDOW_SIZE: usize = 2048;
+const SPECTROGRAM_HOP_SIZE: usize = SPECTROGRAM_WINDOW_SIZE / 4;
+
+// renders `source` offline and writes a grayscale PGM (P5) spectrogram to
+// `path`: columns are successive, overlapping `SPECTROGRAM_WINDOW_SIZE`-
+// sample windows advanced by `SPECTROGRAM_HOP_SIZE` samples; rows are FFT
+// bins from Nyquist (top) down to DC (bottom); brightness is each bin's
+// magnitude, log-scaled and normalized to the loudest bin in the whole
+// render. Meant for eyeballing timbre/aliasing (e.g. comparing a naive
+// waveform's spectrum against its anti-aliased counterpart), not for
+// precise measurement
+pub fn render_to_spectrogram(source: &mut Source, path: &str, sample_rate: u32) -> std::io::Result<()> {
+	let samples: Vec<f64> = source.samples(sample_rate).collect();
+
+	let bins = SPECTROGRAM_WINDOW_SIZE / 2;
+	let window_count = if samples.len() > SPECTROGRAM_WINDOW_SIZE {
+		(samples.len() - SPECTROGRAM_WINDOW_SIZE) / SPECTROGRAM_HOP_SIZE + 1
+	} else {
+		1
+	};
+
+	let mut magnitudes = vec![0.0; window_count * bins];
+	let mut loudest: f64 = 0.0;
+
+	for window in 0 .. window_count {
+		let start = window * SPECTROGRAM_HOP_SIZE;
+		let mut column = vec![(0.0, 0.0); SPECTROGRAM_WINDOW_SIZE];
+
+		for i in 0 .. SPECTROGRAM_WINDOW_SIZE {
+			let sample = samples.get(start + i).copied().unwrap_or(0.0);
+			let hann = 0.5 - 0.5 * (TAU * i as f64 / (SPECTROGRAM_WINDOW_SIZE - 1) as f64).cos();
+
+			column[i] = (sample * hann, 0.0);
+		}
+
+		fft(&mut column);
+
+		for bin in 0 .. bins {
+			let (re, im) = column[bin];
+			let magnitude = (re * re + im * im).sqrt();
+
+			magnitudes[window * bins + bin] = magnitude;
+			loudest = loudest.max(magnitude);
+		}
+	}
+
+	write_spectrogram_pgm(path, window_count, bins, &magnitudes, loudest)
+}
+
+// an in-place, iterative radix-2 Cooley-Tukey FFT over `data` (interpreted
+// as (real, imaginary) pairs). `data.len()` must be a power of two
+fn fft(data: &mut [(f64, f64)]) {
+	let n = data.len();
+
+	if n <= 1 {
+		return;
+	}
+
+	assert!(n.is_power_of_two(), "fft length must be a power of two");
+
+	// bit-reversal permutation
+	let mut j = 0;
+
+	for i in 1 .. n {
+		let mut bit = n >> 1;
+
+		while j & bit != 0 {
+			j ^= bit;
+			bit >>= 1;
+		}
+
+		j ^= bit;
+
+		if i < j {
+			data.swap(i, j);
+		}
+	}
+
+	let mut len = 2;
+
+	while len <= n {
+		let angle = -TAU / len as f64;
+		let (w_re, w_im) = (angle.cos(), angle.sin());
+
+		let mut start = 0;
+
+		while start < n {
+			let (mut cur_re, mut cur_im) = (1.0, 0.0);
+
+			for k in 0 .. len / 2 {
+				let (even_re, even_im) = data[start + k];
+				let (odd_re, odd_im) = data[start + k + len / 2];
+
+				let twiddled_re = odd_re * cur_re - odd_im * cur_im;
+				let twiddled_im = odd_re * cur_im + odd_im * cur_re;
+
+				data[start + k] = (even_re + twiddled_re, even_im + twiddled_im);
+				data[start + k + len / 2] = (even_re - twiddled_re, even_im - twiddled_im);
+
+				let next_re = cur_re * w_re - cur_im * w_im;
+				let next_im = cur_re * w_im + cur_im * w_re;
+
+				cur_re = next_re;
+				cur_im = next_im;
+			}
+
+			start += len;
+		}
+
+		len <<= 1;
+	}
+}
+
+fn write_spectrogram_pgm(path: &str, width: usize, height: usize, magnitudes: &[f64], loudest: f64) -> std::io::Result<()> {
+	let mut file = File::create(path)?;
+
+	file.write_all(format!("P5\n{} {}\n255\n", width, height).as_bytes())?;
+
+	// log-scaled so quiet harmonics stay visible next to the fundamental,
+	// with a floor well below the loudest bin standing in for silence
+	let floor_db = -60.0f64;
+	let loudest = loudest.max(1e-12);
+
+	for row in 0 .. height {
+		// row 0 is the top of the image, which should be the highest
+		// frequency, so walk the bins in reverse
+		let bin = height - 1 - row;
+
+		for column in 0 .. width {
+			let magnitude = magnitudes[column * height + bin];
+			let db = 20.0 * (magnitude.max(1e-12) / loudest).log10();
+			let level = ((db - floor_db) / -floor_db).max(0.0).min(1.0);
+
+			file.write_all(&[(level * 255.0) as u8])?;
+		}
+	}
+
+	Ok(())
+}
+
+// `samples` is already interleaved per `channels` (i.e. `samples.len()` is a
+// multiple of `channels`, and for stereo the order is left, right, left,
+// right, ...)
+fn write_wav(path: &str, sample_rate: u32, channels: u16, samples: &[f64]) -> std::io::Result<()> {
+	let bits_per_sample: u16 = 16;
+	let byte_rate = sample_rate * u32::from(channels) * u32::from(bits_per_sample) / 8;
+	let block_align = channels * bits_per_sample / 8;
+	let data_size = (samples.len() * 2) as u32;
+
+	let mut file = File::create(path)?;
+
+	file.write_all(b"RIFF")?;
+	file.write_all(&(36 + data_size).to_le_bytes())?;
+	file.write_all(b"WAVE")?;
+
+	file.write_all(b"fmt ")?;
+	file.write_all(&16u32.to_le_bytes())?;
+	file.write_all(&1u16.to_le_bytes())?;
+	file.write_all(&channels.to_le_bytes())?;
+	file.write_all(&sample_rate.to_le_bytes())?;
+	file.write_all(&byte_rate.to_le_bytes())?;
+	file.write_all(&block_align.to_le_bytes())?;
+	file.write_all(&bits_per_sample.to_le_bytes())?;
+
+	file.write_all(b"data")?;
+	file.write_all(&data_size.to_le_bytes())?;
+
+	for &sample in samples {
+		let clamped = sample.max(-1.0).min(1.0);
+		let value = (clamped * f64::from(i16::MAX)) as i16;
+
+		file.write_all(&value.to_le_bytes())?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// a `Source` with no tracks at all should play a brief silence rather
+	// than terminating on the very first sample (see `EMPTY_SOURCE_SILENCE_MS`)
+	#[test]
+	fn empty_source_plays_brief_silence_then_ends() {
+		let mut source = Source::new(vec![], 120.0, Some(1));
+		let mut silent_samples = 0;
+
+		while let Some(signal) = play_source(0.0, &mut source, 44100) {
+			assert_eq!(signal, (0.0, 0.0));
+			silent_samples += 1;
+		}
+
+		assert!(silent_samples > 0, "an empty source should play some silence before ending");
+	}
+
+	// a `Source` whose only track has no instructions is just as "empty" as
+	// one with no tracks, and should be handled the same way
+	#[test]
+	fn source_with_only_empty_tracks_plays_brief_silence_then_ends() {
+		let track = Track::new(Vec::new(), Waveform::Sin, Adsr::default(), 0.0);
+		let mut source = Source::new(vec![track], 120.0, Some(1));
+		let mut silent_samples = 0;
+
+		while let Some(signal) = play_source(0.0, &mut source, 44100) {
+			assert_eq!(signal, (0.0, 0.0));
+			silent_samples += 1;
+		}
+
+		assert!(silent_samples > 0, "a source with only empty tracks should play some silence before ending");
+	}
+
+	#[test]
+	fn pitch_of_resolves_a4_to_zero() {
+		assert_eq!(pitch_of("A4").unwrap(), 0);
+	}
+
+	#[test]
+	fn pitch_of_crosses_the_octave_boundary_at_c() {
+		// B3 is one semitone below C4, even though the octave number only
+		// ticks over at C
+		assert_eq!(pitch_of("B3").unwrap(), pitch_of("C4").unwrap() - 1);
+	}
+
+	#[test]
+	fn pitch_of_treats_sharps_and_flats_as_enharmonic() {
+		assert_eq!(pitch_of("C#4").unwrap(), pitch_of("Db4").unwrap());
+		assert_eq!(pitch_of("D#3").unwrap(), pitch_of("Eb3").unwrap());
+	}
+
+	#[test]
+	fn pitch_of_rejects_an_unknown_note_letter() {
+		assert!(pitch_of("H4").is_err());
+	}
+
+	// a NaN voice (here from a pathological NaN `Adsr::curve`, which the
+	// attack ramp's `powf` doesn't clamp the way velocity is elsewhere)
+	// mixed in alongside a normal one shouldn't panic `play_source`'s
+	// voice-stealing sort once `max_voices` forces it to run
+	#[test]
+	fn play_source_does_not_panic_when_a_nan_voice_gets_stolen() {
+		let note = || Instruction::Note {
+			pitch: 0, length: WHOLE, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None,
+		};
+
+		let nan_adsr = Adsr {attack: 0.5, decay: 0.0, sustain: 1.0, release: 0.05, curve: f64::NAN};
+
+		let tracks = vec![
+			Track::new(vec![note()], Waveform::Sin, Adsr::default(), 0.0),
+			Track::new(vec![note()], Waveform::Sin, nan_adsr, 0.0),
+		];
+
+		let mut source = Source::new(tracks, 120.0, Some(1)).with_max_voices(1);
+		let sample_rate = 44100;
+
+		for i in 0 .. sample_rate {
+			let t = i as f64 / f64::from(sample_rate);
+
+			play_source(t, &mut source, sample_rate);
+		}
+	}
+
+	// negative and large inputs should wrap via rem_euclid instead of
+	// falling through every branch and hitting the unreachable!()
+	#[test]
+	fn sawtooth_handles_negative_and_large_inputs() {
+		assert_eq!(sawtooth(-0.25), sawtooth(0.75));
+		assert_eq!(sawtooth(-1.0), sawtooth(0.0));
+		assert_eq!(sawtooth(10.25), sawtooth(0.25));
+	}
+
+	// `Source::samples` should yield exactly the mono collapse of what
+	// `play_source` itself produces, sample for sample
+	#[test]
+	fn samples_matches_play_source_collapsed_to_mono() {
+		let note = || Instruction::Note {
+			pitch: 0, length: 0.25, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None,
+		};
+
+		let mut via_samples = Source::new(vec![Track::new(vec![note()], Waveform::Sin, Adsr::default(), 0.0)], 120.0, Some(1));
+		let mut via_play_source = Source::new(vec![Track::new(vec![note()], Waveform::Sin, Adsr::default(), 0.0)], 120.0, Some(1));
+
+		let sample_rate = 44100;
+		let mut samples = via_samples.samples(sample_rate);
+
+		for i in 0 .. 100 {
+			let t = i as f64 / sample_rate as f64;
+			let expected = play_source(t, &mut via_play_source, sample_rate).map(|(l, r)| (l + r) * 0.5);
+
+			assert_eq!(samples.next(), expected);
+		}
+	}
+
+	// `time_origin` rebases `t` back down to the current loop pass before it
+	// reaches any track (see its doc comment), so a source that's been
+	// playing for a long time should sound identical to one just starting,
+	// sample for sample, once `time_origin` accounts for the elapsed time
+	#[test]
+	fn phase_matches_after_a_long_time_origin_offset() {
+		let note = || Instruction::Note {
+			pitch: 0, length: 0.25, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None,
+		};
+
+		let sample_rate = 44100;
+		let mut fresh = Source::new(vec![Track::new(vec![note()], Waveform::Sin, Adsr::default(), 0.0)], 120.0, Some(1));
+		let mut far = Source::new(vec![Track::new(vec![note()], Waveform::Sin, Adsr::default(), 0.0)], 120.0, Some(1));
+
+		far.time_origin = 100_000.0;
+
+		for i in 0 .. 1000 {
+			let t = i as f64 / sample_rate as f64;
+
+			let fresh_sample = play_source(t, &mut fresh, sample_rate).unwrap();
+			let far_sample = play_source(100_000.0 + t, &mut far, sample_rate).unwrap();
+
+			assert!(
+				(fresh_sample.0 - far_sample.0).abs() < 1e-9 && (fresh_sample.1 - far_sample.1).abs() < 1e-9,
+				"sample {} diverged after a large time_origin offset: {:?} vs {:?}", i, fresh_sample, far_sample,
+			);
+		}
+	}
+
+	// under `PanLaw::Linear`, a center-panned note should come out
+	// bit-identical in both channels of a stereo render, per
+	// `render_to_wav`'s doc comment (this doesn't hold under the default
+	// `PanLaw::ConstantPower`, which scales by `cos`/`sin` of the pan angle
+	// instead of an exact 0.5/0.5 split)
+	#[test]
+	fn stereo_samples_of_a_centered_note_are_bit_identical() {
+		let note = || Instruction::Note {
+			pitch: 0, length: 0.25, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None,
+		};
+
+		let mut source = Source::new(vec![Track::new(vec![note()], Waveform::Sin, Adsr::default(), 0.0)], 120.0, Some(1))
+			.with_pan_law(PanLaw::Linear);
+		let sample_rate = 44100;
+		let mut saw_a_sample = false;
+
+		for (left, right) in source.stereo_samples(sample_rate) {
+			assert_eq!(left, right, "a centered note's channels should be bit-identical");
+			saw_a_sample = true;
+		}
+
+		assert!(saw_a_sample, "the source should have produced at least one sample");
+	}
+
+	// `bpm` is shared across every track on a `Source`, so a `Tempo`
+	// instruction firing on another track mid-note must invalidate this
+	// track's cached `InstructionTiming`, not just leave it keyed on
+	// `current_instruction`/`start_of_instruction` — otherwise the cached
+	// `current_length` used for crossfade math goes stale relative to the
+	// live `measure_time` the advancement loop actually uses to end the note
+	#[test]
+	fn instruction_timing_cache_tracks_a_bpm_change_mid_note() {
+		let note = Instruction::Note {
+			pitch: 0, length: WHOLE, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None,
+		};
+
+		let mut track = Track::new(vec![note], Waveform::Sin, Adsr::default(), 0.0);
+		let mut bpm = 120.0;
+		let sample_rate = 44100;
+
+		play_track(0.0, &mut track, &mut bpm, sample_rate, 0.0, DEFAULT_TUNING, &Temperament::EqualTemperament, 100.0, 0.0, 0.0);
+
+		let measure_time_before = track.instruction_timing.as_ref().unwrap().measure_time;
+
+		// simulate a different track's `Tempo` instruction halving the shared
+		// bpm while this track is still mid-note
+		bpm = 60.0;
+
+		play_track(0.01, &mut track, &mut bpm, sample_rate, 0.0, DEFAULT_TUNING, &Temperament::EqualTemperament, 100.0, 0.0, 0.0);
+
+		let timing = track.instruction_timing.as_ref().unwrap();
+
+		assert_ne!(timing.measure_time, measure_time_before, "the cache should have picked up the new tempo");
+		assert_eq!(timing.measure_time, 60.0 / bpm * 4.0, "the cache should reflect the live bpm, not a stale one");
+	}
+
+	// the cache exists purely as an optimization; it should agree with the
+	// analytic formula it precomputes, both inside and outside its cached
+	// range
+	#[test]
+	fn equal_temperament_ratio_matches_the_analytic_formula() {
+		for pitch in [-PITCH_CACHE_RANGE - 5, -PITCH_CACHE_RANGE, -12, 0, 7, 12, PITCH_CACHE_RANGE, PITCH_CACHE_RANGE + 5] {
+			let expected = 2.0f64.powf(1.0 / 12.0).powi(pitch);
+
+			assert_eq!(equal_temperament_ratio(pitch), expected, "pitch {} diverged from the analytic ratio", pitch);
+		}
+	}
+
+	// a track of 4 quarter notes at the default polyrhythm (1.0) and a track
+	// of 3 quarter notes at polyrhythm 4.0/3.0 (see its doc comment's own
+	// worked example) should span the same wall-clock time, ending together
+	// rather than drifting apart sample by sample
+	#[test]
+	fn three_against_four_polyrhythm_ends_both_tracks_together() {
+		let note = || Instruction::Note {
+			pitch: 0, length: QUARTER, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None,
+		};
+
+		let mut track_a = Track::new(vec![note(), note(), note(), note()], Waveform::Sin, Adsr::default(), 0.0);
+		let mut track_b = Track::new(vec![note(), note(), note()], Waveform::Sin, Adsr::default(), 0.0)
+			.with_polyrhythm(4.0 / 3.0);
+
+		let sample_rate = 44100;
+		let mut bpm_a = 120.0;
+		let mut bpm_b = 120.0;
+
+		let mut last_sound_a = 0.0;
+		let mut last_sound_b = 0.0;
+
+		// both tracks span 2.0s, so run a second past that to see them end
+		for i in 0 .. 3 * sample_rate {
+			let t = i as f64 / f64::from(sample_rate);
+
+			if play_track(t, &mut track_a, &mut bpm_a, sample_rate, 0.0, DEFAULT_TUNING, &Temperament::EqualTemperament, 0.0, 0.0, 0.0).is_some() {
+				last_sound_a = t;
+			}
+
+			if play_track(t, &mut track_b, &mut bpm_b, sample_rate, 0.0, DEFAULT_TUNING, &Temperament::EqualTemperament, 0.0, 0.0, 0.0).is_some() {
+				last_sound_b = t;
+			}
+		}
+
+		assert!(
+			(last_sound_a - last_sound_b).abs() < 2.0 / f64::from(sample_rate),
+			"tracks ended {} samples apart", (last_sound_a - last_sound_b) * f64::from(sample_rate),
+		);
+	}
+
+	// the sine wavetable is a linearly-interpolated sample of `sin_wave`, so
+	// it should track the analytic function closely everywhere, not just at
+	// the sampled points
+	#[test]
+	fn sine_table_matches_sin_wave_within_tolerance() {
+		for i in 0 .. 1000 {
+			let phase = i as f64 / 1000.0;
+
+			assert!(
+				(table_lookup(sine_table(), phase) - sin_wave(phase)).abs() < 1e-3,
+				"phase {} diverged from the analytic sine", phase,
+			);
+		}
+	}
+}
@@ -1,62 +1,841 @@
 use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
 use cpal::{StreamData, UnknownTypeOutputBuffer as UTOB};
-use std::f64::consts::PI;
 use std::thread;
 use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use megalovania::{Adsr, Instruction, PanLaw, Source, Temperament, TimeSignature, Track, Tremolo, Vibrato, Waveform, FULL_VELOCITY, HALF, N16TH, N8TH, QUARTER, WHOLE};
+use megalovania::{dotted, parse_track, play_source, render_to_raw, render_to_spectrogram, render_to_wav, sin_wave};
+
+const WAV_SAMPLE_RATE: u32 = 44100;
+const BPM: f64 = 140.0;
+
+// --tone's default frequency when none is given, a common reference pitch
+// for speaker/headphone calibration
+const DEFAULT_TONE_HZ: f64 = 1000.0;
+
+// maps a linear 0.0..=1.0 `--volume` knob to the multiplier actually passed
+// to `Source::with_volume`. Perceived loudness is roughly logarithmic, not
+// linear, so a straight multiplier bunches most of the audible change into
+// the top of the range; squaring it spreads that change out more evenly
+// across the knob, which is the cheapest curve that still fixes the feel
+// without pulling in a dB/log dependency
+fn perceptual_volume(volume: f64) -> f64 {
+	volume * volume
+}
+
+fn print_help() {
+	println!("usage: megalovania [options]");
+	println!();
+	println!("options:");
+	println!("  --track <path>    parse and add an extra track from a notation file");
+	println!("  --output <path>   render to a wav file instead of playing live");
+	println!("  --channels <1|2>  render --output as mono (sum of channels) or true stereo honoring per-track pan (default 1)");
+	println!("  --stdout          render offline and stream headerless raw PCM to stdout instead of playing live (mono, 16-bit signed little-endian, at --sample-rate or {} Hz)", WAV_SAMPLE_RATE);
+	println!("  --analyze <path>  render offline and write a grayscale PGM spectrogram to this path instead of playing live");
+	println!("  --bpm <f64>       override the default tempo ({} bpm)", BPM);
+	println!("  --volume <f64>    override the default volume, clamped to 0.0..=1.0 and mapped through a perceptual (squared) curve before being applied");
+	println!("  --transpose <i32> shift every note by this many semitones");
+	println!("  --sample-rate <u32> request a specific output sample rate in Hz");
+	println!("  --tuning <f64>    override the tuning reference, A4 in Hz (default 440.0)");
+	println!("  --just-intonation <i32> use 5-limit just intonation around this tonic pitch offset");
+	println!("  --crossfade <f64> crossfade this many milliseconds between consecutive instructions");
+	println!("  --reverb <f64>    mix in a Schroeder reverb, 0.0..1.0");
+	println!("  --humanize <f64>  nudge each note's timing/velocity slightly so playback doesn't sound quantized, 0.0..1.0");
+	println!("  --max-voices <u32> cap simultaneous tracks/drum hits, stealing the quietest ones past the limit");
+	println!("  --time-signature <n>/<d> override the time signature (default 4/4)");
+	println!("  --device <name>   select an output device by (partial, case-insensitive) name, instead of the system default");
+	println!("  --list-devices    print the available output devices and exit");
+	println!("  --pan-law <linear|constant-power> choose how panning distributes across L/R (default constant-power)");
+	println!("  --start <f64>     seek this many seconds into the song before playback/render starts");
+	println!("  --solo <index>    silence every track except the one at this index");
+	println!("  --bitcrush-bits <u32>       quantize output to this many bits (16 is transparent)");
+	println!("  --bitcrush-downsample <u32> hold each sample this many output frames (1 is transparent)");
+	println!("  --distortion-drive <f64>    overdrive gain fed into a tanh soft-clip (1.0 is transparent)");
+	println!("  --distortion-mix <f64>      how much of the distorted signal to blend in, 0.0..1.0 (0.0 is transparent)");
+	println!("  --dc-blocker-r <f64>        one-pole DC blocker feedback coefficient, closer to 1.0 filters less bass (default {})", DEFAULT_DC_BLOCKER_R);
+	println!("  --haas-ms <f64>             widen the stereo image by delaying the right channel this many ms (default {}, transparent; ~10.0 is a subtle widening)", DEFAULT_HAAS_MS);
+	println!("  --compressor-threshold <f64> linear amplitude above which the master output is compressed (default {}, transparent)", DEFAULT_COMPRESSOR_THRESHOLD);
+	println!("  --compressor-ratio <f64>    how much the level above the threshold is turned down, e.g. 4.0 for 4:1 (default {}, transparent)", DEFAULT_COMPRESSOR_RATIO);
+	println!("  --compressor-attack-ms <f64>  how quickly the compressor clamps down once above threshold (default {})", DEFAULT_COMPRESSOR_ATTACK_MS);
+	println!("  --compressor-release-ms <f64> how quickly the compressor lets go once back below threshold (default {})", DEFAULT_COMPRESSOR_RELEASE_MS);
+	println!("  --fade-ms <f64>   fade-in/fade-out time in milliseconds at the start/end of playback (live playback only)");
+	println!("  --click           overlay a metronome click on each beat (live playback only)");
+	println!("  --midi-input      bend all currently sounding notes with a connected MIDI controller's pitch wheel (live playback only)");
+	println!("  --scope           show a scrolling ASCII oscilloscope of the output in the terminal (live playback only)");
+	println!("  --tone [f64]      play a steady sine at this frequency in Hz (default {}) at --volume instead of any track, for calibrating speakers/headphones, until Ctrl-C (live playback only)", DEFAULT_TONE_HZ);
+	println!("  --help            print this message and exit");
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+	let args: Vec<String> = std::env::args().collect();
+
+	if args.iter().any(|arg| arg == "--help") {
+		print_help();
+
+		return Ok(());
+	}
+
+	if args.iter().any(|arg| arg == "--list-devices") {
+		let host = cpal::default_host();
+
+		for device in host.output_devices()? {
+			println!("{}", device.name()?);
+		}
+
+		return Ok(());
+	}
+
+	let mut tracks = vec![treble(), bass(), hihat()];
+
+	// song-level settings collected from `--track` files' header blocks
+	// (see `SongMeta`); command-line flags below take precedence over
+	// these, and the last file's value wins if more than one sets the same
+	// field
+	let mut header_title: Option<String> = None;
+	let mut header_bpm: Option<f64> = None;
+	let mut header_time_signature: Option<TimeSignature> = None;
+	let mut header_tuning: Option<f64> = None;
+
+	for (i, arg) in args.iter().enumerate() {
+		if arg == "--track" {
+			let path = args.get(i + 1).expect("--track requires a file path");
+			let contents = std::fs::read_to_string(path).expect("failed to read track file");
+			let (track, meta) = parse_track(&contents).expect("failed to parse track file");
+
+			header_title = meta.title.or(header_title);
+			header_bpm = meta.bpm.or(header_bpm);
+			header_time_signature = meta.time_signature.or(header_time_signature);
+			header_tuning = meta.tuning.or(header_tuning);
+
+			tracks.push(track);
+		}
+	}
+
+	if let Some(title) = &header_title {
+		println!("{}", title);
+	}
+
+	let bpm = match args.iter().position(|arg| arg == "--bpm") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--bpm requires a value");
+
+			value.parse().expect("--bpm expects a floating point number")
+		},
+		None => header_bpm.unwrap_or(BPM),
+	};
+
+	let volume = args.iter().position(|arg| arg == "--volume").map(|pos| {
+		let value = args.get(pos + 1).expect("--volume requires a value");
+		let volume: f64 = value.parse().expect("--volume expects a floating point number");
+
+		if volume < 0.0 || volume > 1.0 {
+			eprintln!("warning: --volume {} is out of range, clamping to 0.0..=1.0", volume);
+		}
+
+		perceptual_volume(volume.max(0.0).min(1.0))
+	});
+
+	let transpose = match args.iter().position(|arg| arg == "--transpose") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--transpose requires a value");
+
+			value.parse().expect("--transpose expects an integer")
+		},
+		None => 0,
+	};
+
+	if let Some(pos) = args.iter().position(|arg| arg == "--solo") {
+		let value = args.get(pos + 1).expect("--solo requires a track index");
+		let index: usize = value.parse().expect("--solo expects an integer");
+
+		if index >= tracks.len() {
+			panic!("--solo index {} is out of range (only {} tracks)", index, tracks.len());
+		}
+
+		let placeholder = Track::new(Vec::new(), Waveform::Sin, Adsr::default(), 0.0);
+		let soloed = std::mem::replace(&mut tracks[index], placeholder).with_soloed(true);
+
+		tracks[index] = soloed;
+	}
+
+	let click = args.iter().any(|arg| arg == "--click");
+	let midi_input = args.iter().any(|arg| arg == "--midi-input");
+	let scope = args.iter().any(|arg| arg == "--scope");
+
+	let fade_ms = match args.iter().position(|arg| arg == "--fade-ms") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--fade-ms requires a value");
+
+			value.parse().expect("--fade-ms expects a floating point number")
+		},
+		None => FADE_MS,
+	};
+
+	let bitcrush_bits = match args.iter().position(|arg| arg == "--bitcrush-bits") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--bitcrush-bits requires a value");
+
+			value.parse().expect("--bitcrush-bits expects an integer")
+		},
+		None => 16,
+	};
+
+	let bitcrush_downsample = match args.iter().position(|arg| arg == "--bitcrush-downsample") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--bitcrush-downsample requires a value");
+
+			value.parse().expect("--bitcrush-downsample expects an integer")
+		},
+		None => 1,
+	};
+
+	let distortion_drive = match args.iter().position(|arg| arg == "--distortion-drive") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--distortion-drive requires a value");
+
+			value.parse().expect("--distortion-drive expects a floating point number")
+		},
+		None => 1.0,
+	};
+
+	let distortion_mix = match args.iter().position(|arg| arg == "--distortion-mix") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--distortion-mix requires a value");
+
+			value.parse().expect("--distortion-mix expects a floating point number")
+		},
+		None => 0.0,
+	};
+
+	let dc_blocker_r = match args.iter().position(|arg| arg == "--dc-blocker-r") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--dc-blocker-r requires a value");
+
+			value.parse().expect("--dc-blocker-r expects a floating point number")
+		},
+		None => DEFAULT_DC_BLOCKER_R,
+	};
+
+	let haas_ms = match args.iter().position(|arg| arg == "--haas-ms") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--haas-ms requires a value");
+
+			value.parse().expect("--haas-ms expects a floating point number of milliseconds")
+		},
+		None => DEFAULT_HAAS_MS,
+	};
+
+	let compressor_threshold = match args.iter().position(|arg| arg == "--compressor-threshold") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--compressor-threshold requires a value");
+
+			value.parse().expect("--compressor-threshold expects a floating point number")
+		},
+		None => DEFAULT_COMPRESSOR_THRESHOLD,
+	};
+
+	let compressor_ratio = match args.iter().position(|arg| arg == "--compressor-ratio") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--compressor-ratio requires a value");
+
+			value.parse().expect("--compressor-ratio expects a floating point number")
+		},
+		None => DEFAULT_COMPRESSOR_RATIO,
+	};
+
+	let compressor_attack_ms = match args.iter().position(|arg| arg == "--compressor-attack-ms") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--compressor-attack-ms requires a value");
+
+			value.parse().expect("--compressor-attack-ms expects a floating point number")
+		},
+		None => DEFAULT_COMPRESSOR_ATTACK_MS,
+	};
+
+	let compressor_release_ms = match args.iter().position(|arg| arg == "--compressor-release-ms") {
+		Some(pos) => {
+			let value = args.get(pos + 1).expect("--compressor-release-ms requires a value");
+
+			value.parse().expect("--compressor-release-ms expects a floating point number")
+		},
+		None => DEFAULT_COMPRESSOR_RELEASE_MS,
+	};
+
+	let requested_sample_rate: Option<u32> = args.iter().position(|arg| arg == "--sample-rate").map(|pos| {
+		let value = args.get(pos + 1).expect("--sample-rate requires a value");
+
+		value.parse().expect("--sample-rate expects a positive integer")
+	});
+
+	let channels: u16 = args.iter().position(|arg| arg == "--channels").map(|pos| {
+		let value = args.get(pos + 1).expect("--channels requires a value");
+
+		match value.as_str() {
+			"1" => 1,
+			"2" => 2,
+			_ => panic!("--channels expects either \"1\" or \"2\""),
+		}
+	}).unwrap_or(1);
+
+	let tuning = args.iter().position(|arg| arg == "--tuning").map(|pos| {
+		let value = args.get(pos + 1).expect("--tuning requires a value");
+
+		value.parse().expect("--tuning expects a floating point number")
+	}).or(header_tuning);
+
+	let temperament = args.iter().position(|arg| arg == "--just-intonation").map(|pos| {
+		let value = args.get(pos + 1).expect("--just-intonation requires a tonic pitch offset");
+		let tonic = value.parse().expect("--just-intonation expects an integer pitch offset");
+
+		Temperament::JustIntonation {tonic}
+	});
+
+	let crossfade_ms = args.iter().position(|arg| arg == "--crossfade").map(|pos| {
+		let value = args.get(pos + 1).expect("--crossfade requires a value");
+
+		value.parse().expect("--crossfade expects a floating point number of milliseconds")
+	});
+
+	let reverb_mix = args.iter().position(|arg| arg == "--reverb").map(|pos| {
+		let value = args.get(pos + 1).expect("--reverb requires a mix value");
+
+		value.parse().expect("--reverb expects a floating point mix amount")
+	});
+
+	let humanize = args.iter().position(|arg| arg == "--humanize").map(|pos| {
+		let value = args.get(pos + 1).expect("--humanize requires a value");
+
+		value.parse().expect("--humanize expects a floating point number")
+	});
+
+	let max_voices = args.iter().position(|arg| arg == "--max-voices").map(|pos| {
+		let value = args.get(pos + 1).expect("--max-voices requires a value");
+
+		value.parse().expect("--max-voices expects an integer")
+	});
+
+	let start_seconds = args.iter().position(|arg| arg == "--start").map(|pos| {
+		let value = args.get(pos + 1).expect("--start requires a value");
+
+		value.parse().expect("--start expects a floating point number of seconds")
+	});
+
+	let time_signature = args.iter().position(|arg| arg == "--time-signature").map(|pos| {
+		let value = args.get(pos + 1).expect("--time-signature requires a value like 3/4");
+		let mut parts = value.splitn(2, '/');
+
+		let numerator = parts.next().expect("--time-signature requires a numerator")
+			.parse().expect("--time-signature numerator must be an integer");
+		let denominator = parts.next().expect("--time-signature requires a denominator, e.g. 3/4")
+			.parse().expect("--time-signature denominator must be an integer");
+
+		TimeSignature {numerator, denominator}
+	}).or(header_time_signature);
+
+	let pan_law = args.iter().position(|arg| arg == "--pan-law").map(|pos| {
+		let value = args.get(pos + 1).expect("--pan-law requires a value");
+
+		match value.as_str() {
+			"linear" => PanLaw::Linear,
+			"constant-power" => PanLaw::ConstantPower,
+			_ => panic!("--pan-law expects either \"linear\" or \"constant-power\""),
+		}
+	});
+
+	if let Some(pos) = args.iter().position(|arg| arg == "--output") {
+		let path = args.get(pos + 1).expect("--output requires a file path");
+		let mut source = Source::new(tracks, bpm, Some(2));
+
+		source.transpose(transpose);
+
+		if let Some(volume) = volume {
+			source = source.with_volume(volume);
+		}
+
+		if let Some(tuning) = tuning {
+			source = source.with_tuning(tuning);
+		}
+
+		if let Some(temperament) = temperament {
+			source = source.with_temperament(temperament);
+		}
+
+		if let Some(crossfade_ms) = crossfade_ms {
+			source = source.with_crossfade(crossfade_ms);
+		}
+
+		if let Some(reverb_mix) = reverb_mix {
+			source = source.with_reverb(reverb_mix);
+		}
+
+		if let Some(humanize) = humanize {
+			source = source.with_humanize(humanize);
+		}
+
+		if let Some(max_voices) = max_voices {
+			source = source.with_max_voices(max_voices);
+		}
+
+		if let Some(time_signature) = time_signature {
+			source = source.with_time_signature(time_signature);
+		}
+
+		if let Some(pan_law) = pan_law {
+			source = source.with_pan_law(pan_law);
+		}
+
+		if let Some(start_seconds) = start_seconds {
+			source.seek(start_seconds);
+		}
+
+		// the offline renderer always honors the requested rate exactly,
+		// there's no hardware format to negotiate with
+		let sample_rate = requested_sample_rate.unwrap_or(WAV_SAMPLE_RATE);
+
+		render_to_wav(&mut source, path, sample_rate, channels).expect("failed to render wav file");
+
+		return Ok(());
+	}
+
+	// same offline render as `--output`, but streamed to stdout as headerless
+	// raw PCM (mono, 16-bit signed little-endian, at `--sample-rate` or
+	// WAV_SAMPLE_RATE) instead of a WAV file, for piping into another tool
+	if args.iter().any(|arg| arg == "--stdout") {
+		let mut source = Source::new(tracks, bpm, Some(2));
+
+		source.transpose(transpose);
+
+		if let Some(volume) = volume {
+			source = source.with_volume(volume);
+		}
+
+		if let Some(tuning) = tuning {
+			source = source.with_tuning(tuning);
+		}
+
+		if let Some(temperament) = temperament {
+			source = source.with_temperament(temperament);
+		}
+
+		if let Some(crossfade_ms) = crossfade_ms {
+			source = source.with_crossfade(crossfade_ms);
+		}
+
+		if let Some(reverb_mix) = reverb_mix {
+			source = source.with_reverb(reverb_mix);
+		}
+
+		if let Some(humanize) = humanize {
+			source = source.with_humanize(humanize);
+		}
+
+		if let Some(max_voices) = max_voices {
+			source = source.with_max_voices(max_voices);
+		}
+
+		if let Some(time_signature) = time_signature {
+			source = source.with_time_signature(time_signature);
+		}
+
+		if let Some(pan_law) = pan_law {
+			source = source.with_pan_law(pan_law);
+		}
+
+		if let Some(start_seconds) = start_seconds {
+			source.seek(start_seconds);
+		}
+
+		let sample_rate = requested_sample_rate.unwrap_or(WAV_SAMPLE_RATE);
+
+		let stdout = std::io::stdout();
+		let mut lock = stdout.lock();
+
+		render_to_raw(&mut source, &mut lock, sample_rate).expect("failed to write raw PCM to stdout");
+
+		return Ok(());
+	}
+
+	// same offline render as `--output`, but written out as a spectrogram
+	// image instead of audio, for inspecting timbre/aliasing
+	if let Some(pos) = args.iter().position(|arg| arg == "--analyze") {
+		let path = args.get(pos + 1).expect("--analyze requires a file path");
+		let mut source = Source::new(tracks, bpm, Some(2));
+
+		source.transpose(transpose);
+
+		if let Some(volume) = volume {
+			source = source.with_volume(volume);
+		}
+
+		if let Some(tuning) = tuning {
+			source = source.with_tuning(tuning);
+		}
+
+		if let Some(temperament) = temperament {
+			source = source.with_temperament(temperament);
+		}
+
+		if let Some(crossfade_ms) = crossfade_ms {
+			source = source.with_crossfade(crossfade_ms);
+		}
+
+		if let Some(reverb_mix) = reverb_mix {
+			source = source.with_reverb(reverb_mix);
+		}
+
+		if let Some(humanize) = humanize {
+			source = source.with_humanize(humanize);
+		}
+
+		if let Some(max_voices) = max_voices {
+			source = source.with_max_voices(max_voices);
+		}
+
+		if let Some(time_signature) = time_signature {
+			source = source.with_time_signature(time_signature);
+		}
+
+		if let Some(pan_law) = pan_law {
+			source = source.with_pan_law(pan_law);
+		}
+
+		if let Some(start_seconds) = start_seconds {
+			source.seek(start_seconds);
+		}
+
+		let sample_rate = requested_sample_rate.unwrap_or(WAV_SAMPLE_RATE);
+
+		render_to_spectrogram(&mut source, path, sample_rate).expect("failed to write spectrogram");
+
+		return Ok(());
+	}
 
-fn main() {
 	let host = cpal::default_host();
 	let event_loop = host.event_loop();
-	let device = host.default_output_device().expect("no output device found");
 
-	let mut supported_formats_range = device
-		.supported_output_formats()
-		.expect("error while querying formats");
-	let format = supported_formats_range.next()
-		.expect("no format supported")
-		.with_max_sample_rate();
+	let device_name = args.iter().position(|arg| arg == "--device").map(|pos| {
+		args.get(pos + 1).expect("--device requires a name").clone()
+	});
+
+	let device = match &device_name {
+		Some(name) => {
+			let matching = host.output_devices()?.find(|device| {
+				device.name().map(|device_name| {
+					device_name.to_lowercase().contains(&name.to_lowercase())
+				}).unwrap_or(false)
+			});
+
+			match matching {
+				Some(device) => device,
+				None => {
+					eprintln!("warning: no output device matching \"{}\", falling back to the default", name);
+
+					host.default_output_device().ok_or("no output device found")?
+				},
+			}
+		},
+		None => host.default_output_device().ok_or("no output device found")?,
+	};
+
+	let supported_formats_range = device.supported_output_formats()?;
+	let format = match requested_sample_rate {
+		Some(rate) => {
+			let formats: Vec<_> = supported_formats_range.collect();
+
+			let matching = formats.iter()
+				.find(|format| format.min_sample_rate.0 <= rate && rate <= format.max_sample_rate.0);
+
+			match matching {
+				Some(format) => format.clone().with_sample_rate(cpal::SampleRate(rate)),
+				None => {
+					eprintln!("warning: no output format supports {} Hz, falling back to the default", rate);
+
+					formats.into_iter().next()
+						.ok_or("no output format supported")?
+						.with_max_sample_rate()
+				},
+			}
+		},
+		None => supported_formats_range.into_iter().next().ok_or("no output format supported")?
+			.with_max_sample_rate(),
+	};
 
 	let sample_rate = format.sample_rate.0;
 	let channel_count = usize::from(format.channels);
 
-	let stream_id = event_loop.build_output_stream(&device, &format).unwrap();
+	let stream_id = event_loop.build_output_stream(&device, &format)?;
+
+	event_loop.play_stream(stream_id)?;
+
+	// bypasses `Source`, tracks, and every effect entirely: a steady sine at
+	// `--volume` (the same 0.1 default `Source` itself uses) for checking a
+	// monitor/speaker for crackle or calibrating levels, until Ctrl-C
+	if let Some(pos) = args.iter().position(|arg| arg == "--tone") {
+		let frequency = args.get(pos + 1).map(|value| {
+			value.parse().expect("--tone expects a frequency in Hz")
+		}).unwrap_or(DEFAULT_TONE_HZ);
+
+		let amplitude = volume.unwrap_or(0.1);
+		let mut t = 0.0;
+		let dt = 1.0 / f64::from(sample_rate);
+
+		event_loop.run(move |_stream_id, stream_result| {
+			let stream_data = match stream_result {
+				Ok(data) => data,
+				Err(error) => {
+					eprintln!("warning: an error occurred on the tone stream: {}", error);
+
+					return;
+				},
+			};
 
-	event_loop.play_stream(stream_id).expect("failed to play stream");
+			if let StreamData::Output {buffer: buffer_enum} = stream_data {
+				match buffer_enum {
+					UTOB::U16(mut buffer) => fill_tone(&mut buffer, channel_count, &mut t, dt, frequency, amplitude, |f| {
+						((1.0 + f) * f64::from(u16::MAX / 2)) as u16
+					}),
+					UTOB::I16(mut buffer) => fill_tone(&mut buffer, channel_count, &mut t, dt, frequency, amplitude, |f| {
+						(f * f64::from(i16::MAX)) as i16
+					}),
+					UTOB::F32(mut buffer) => fill_tone(&mut buffer, channel_count, &mut t, dt, frequency, amplitude, |f| {
+						f as f32
+					}),
+				}
+			}
+		});
+	}
 
 	let mut counter: u64 = 0;
 
-	let mut source = Source {tracks: vec![treble(), bass()]};
+	let mut source = Source::new(tracks, bpm, Some(2));
+
+	source.transpose(transpose);
+
+	if let Some(start_seconds) = start_seconds {
+		source.seek(start_seconds);
+
+		counter = (start_seconds.max(0.0) * f64::from(sample_rate)) as u64;
+	}
+
+	if let Some(volume) = volume {
+		source = source.with_volume(volume);
+	}
+
+	if let Some(tuning) = tuning {
+		source = source.with_tuning(tuning);
+	}
+
+	if let Some(temperament) = temperament {
+		source = source.with_temperament(temperament);
+	}
+
+	if let Some(crossfade_ms) = crossfade_ms {
+		source = source.with_crossfade(crossfade_ms);
+	}
+
+	if let Some(reverb_mix) = reverb_mix {
+		source = source.with_reverb(reverb_mix);
+	}
+
+	if let Some(humanize) = humanize {
+		source = source.with_humanize(humanize);
+	}
+
+	if let Some(max_voices) = max_voices {
+		source = source.with_max_voices(max_voices);
+	}
+
+	if let Some(time_signature) = time_signature {
+		source = source.with_time_signature(time_signature);
+	}
+
+	if let Some(pan_law) = pan_law {
+		source = source.with_pan_law(pan_law);
+	}
+
+	let metronome = if click {
+		Some(Metronome::new(&source))
+	} else {
+		None
+	};
+
+	// shared with the MIDI listener thread (if any); read once per buffer in
+	// `fill_buffer` and fed into `Source::set_bend_semitones`
+	let bend = Arc::new(Mutex::new(0.0));
+
+	if midi_input {
+		spawn_pitch_bend_listener(Arc::clone(&bend));
+	}
+
+	// shared with the oscilloscope thread (if `--scope` is given); `None`
+	// otherwise, so `fill_buffer` skips the lock entirely when it's off
+	let scope_buffer = if scope {
+		let buffer = Arc::new(Mutex::new(ScopeBuffer::new(SCOPE_BUFFER_LEN)));
+
+		spawn_scope_thread(Arc::clone(&buffer));
+
+		Some(buffer)
+	} else {
+		None
+	};
 
-	let (tx, rx) = channel();
+	let (tx, rx) = channel::<Result<(), String>>();
+
+	// spacebar transport control: reading is line-buffered (no extra
+	// dependency for raw terminal mode), so the user types a space and
+	// hits enter to toggle. `fill_buffer` ramps the gain over PAUSE_FADE_MS
+	// on either side of the flag flipping, so there's no click
+	let paused = Arc::new(AtomicBool::new(false));
+
+	// live waveform switching: '1'/'2'/'3'/'4' select sine/saw/square/
+	// triangle. Read once per buffer in `fill_buffer` and fed into
+	// `Source::set_waveform`, which itself defers the swap to each track's
+	// next note boundary so it never chops a note mid-cycle
+	let waveform_select: Arc<Mutex<Option<Waveform>>> = Arc::new(Mutex::new(None));
+
+	thread::spawn({
+		let paused = Arc::clone(&paused);
+		let waveform_select = Arc::clone(&waveform_select);
+
+		move || {
+			let stdin = std::io::stdin();
+			let mut line = String::new();
+
+			loop {
+				line.clear();
+
+				if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+					break;
+				}
+
+				if line.contains(' ') {
+					let was_paused = paused.fetch_xor(true, Ordering::Relaxed);
+
+					println!("{}", if was_paused {"resumed"} else {"paused"});
+				}
+
+				let waveform = line.chars().find_map(|c| match c {
+					'1' => Some(Waveform::Sin),
+					'2' => Some(Waveform::Sawtooth),
+					'3' => Some(Waveform::Square),
+					'4' => Some(Waveform::Triangle),
+					_ => None,
+				});
+
+				if let Some(waveform) = waveform {
+					*waveform_select.lock().unwrap() = Some(waveform);
+
+					println!("waveform switched");
+				}
+			}
+		}
+	});
 
 	thread::spawn(move || {
 		let mut terminating = false;
+		let mut fade = Fade {last_sample: (0.0, 0.0), remaining: None};
+		let mut pause = PauseState {flag: paused, gain: 1.0};
+		let mut bit_crush = BitCrush {
+			bits: bitcrush_bits,
+			downsample: bitcrush_downsample.max(1),
+			held: (0.0, 0.0),
+			counter: 0,
+		};
+		let distortion = Distortion {
+			drive: distortion_drive,
+			mix: distortion_mix,
+		};
+		let mut dc_blocker = DcBlocker {
+			r: dc_blocker_r,
+			previous_input: (0.0, 0.0),
+			previous_output: (0.0, 0.0),
+		};
+		let mut haas = Haas::new(haas_ms, sample_rate);
+		let mut compressor = Compressor::new(compressor_threshold, compressor_ratio, compressor_attack_ms, compressor_release_ms, sample_rate);
+		let mut prime_remaining = ((sample_rate as f64) * PRIME_MS / 1000.0) as u32;
+
+		// the device most commonly disappears because the user unplugged
+		// headphones or switched outputs; a handful of stream errors in a
+		// row is worth riding out by rebuilding on the current default
+		// device before giving up
+		let mut stream_retries: u32 = 0;
 
 		event_loop.run(move |stream_id, stream_result| {
 			if terminating {
-				tx.send(()).expect("thread sending error");
+				tx.send(Ok(())).expect("thread sending error");
 				thread::park();
 			}
 
 			let stream_data = match stream_result {
 				Ok(data) => data,
-				Err(error) => panic!(format!(
-					"an error occured on stream {:?}: {}",
-					stream_id,
-					error,
-				)),
+				Err(error) => {
+					eprintln!(
+						"warning: an error occurred on stream {:?}: {}, attempting to reconnect",
+						stream_id,
+						error,
+					);
+
+					event_loop.destroy_stream(stream_id);
+
+					stream_retries += 1;
+
+					if stream_retries > MAX_STREAM_RETRIES {
+						tx.send(Err(format!(
+							"output stream failed after {} retries: {}",
+							MAX_STREAM_RETRIES,
+							error,
+						))).expect("thread sending error");
+
+						thread::park();
+					}
+
+					let rebuilt = host.default_output_device()
+						.and_then(|device| event_loop.build_output_stream(&device, &format).ok())
+						.and_then(|new_stream_id| {
+							event_loop.play_stream(new_stream_id).ok()
+						});
+
+					if rebuilt.is_none() {
+						tx.send(Err("no output device available after disconnection".to_string()))
+							.expect("thread sending error");
+
+						thread::park();
+					}
+
+					return;
+				},
 			};
 
+			stream_retries = 0;
+
 			let static_data = StaticData {
 				counter: &mut counter,
 				sample_rate,
 				channel_count,
 				source: &mut source,
 				terminating: &mut terminating,
+				fade: &mut fade,
+				pause: &mut pause,
+				bit_crush: &mut bit_crush,
+				distortion: &distortion,
+				dc_blocker: &mut dc_blocker,
+				haas: &mut haas,
+				compressor: &mut compressor,
+				prime_remaining: &mut prime_remaining,
+				metronome: &metronome,
+				bend: &bend,
+				waveform_select: &waveform_select,
+				scope: &scope_buffer,
+				fade_ms,
 			};
 
 			if let StreamData::Output {buffer: buffer_enum} = stream_data {
-				match buffer_enum {
+				let fill_started_at = Instant::now();
+
+				let frame_count = match buffer_enum {
 					UTOB::U16(mut buffer) => {
 						fill_buffer(
 							static_data,
@@ -64,7 +843,9 @@ fn main() {
 							|f| {
 								((1.0 + f) * f64::from(u16::MAX / 2)) as u16
 							},
-						)
+						);
+
+						buffer.len() / channel_count
 					},
 					UTOB::I16(mut buffer) => {
 						fill_buffer(
@@ -73,21 +854,71 @@ fn main() {
 							|f| {
 								(f * f64::from(i16::MAX)) as i16
 							},
-						)
+						);
+
+						buffer.len() / channel_count
 					},
 					UTOB::F32(mut buffer) => {
 						fill_buffer(
 							static_data,
 							&mut *buffer,
 							|f| f as f32,
-						)
+						);
+
+						buffer.len() / channel_count
 					},
-				}
+				};
+
+				warn_if_underrun(fill_started_at.elapsed(), frame_count, sample_rate);
 			}
 		});
 	});
 
-	rx.recv().expect("thread reception error");
+	match rx.recv().expect("thread reception error") {
+		Ok(()) => {},
+		Err(message) => {
+			eprintln!("error: {}", message);
+
+			std::process::exit(1);
+		},
+	}
+
+	Ok(())
+}
+
+// how many consecutive stream errors (e.g. the output device disappearing)
+// to ride out by rebuilding the stream before giving up and exiting
+const MAX_STREAM_RETRIES: u32 = 3;
+
+// how long the fade-out at the end of playback, and the fade-in at the
+// start, take to reach unity gain, overridable with --fade-ms
+const FADE_MS: f64 = 50.0;
+
+// the last real sample played, and how many fade-out samples remain once
+// `play_source` has signalled the end. Lives across cpal buffer callbacks so
+// the ramp isn't reset by every new buffer.
+struct Fade {
+	last_sample: (f64, f64),
+	remaining: Option<u32>,
+}
+
+// how long, after `play_stream`, output is forced to silence outright rather
+// than handed to `closure`/the backend at all. Some backends emit a burst of
+// noise (a pop, a buffer of garbage) in the first callback or two while the
+// hardware spins up; that's a startup artifact, not part of the song, so it
+// gets muted unconditionally rather than blended in like the musical
+// `fade_ms` fade-in above
+const PRIME_MS: f64 = 5.0;
+
+// how long the gain ramp on either side of a pause/resume takes, so toggling
+// the flag mid-buffer doesn't click
+const PAUSE_FADE_MS: f64 = 10.0;
+
+// `gain` ramps between 0.0 (paused) and 1.0 (playing) at PAUSE_FADE_MS,
+// persisting across cpal buffer callbacks the same way `Fade` does
+struct PauseState {
+	flag: Arc<AtomicBool>,
+	gain: f64,
 }
 
 struct StaticData<'a> {
@@ -96,303 +927,686 @@ struct StaticData<'a> {
 	channel_count: usize,
 	source: &'a mut Source,
 	terminating: &'a mut bool,
+	fade: &'a mut Fade,
+	pause: &'a mut PauseState,
+	bit_crush: &'a mut BitCrush,
+	distortion: &'a Distortion,
+	dc_blocker: &'a mut DcBlocker,
+	haas: &'a mut Haas,
+	compressor: &'a mut Compressor,
+	prime_remaining: &'a mut u32,
+	metronome: &'a Option<Metronome>,
+	bend: &'a Arc<Mutex<f64>>,
+	waveform_select: &'a Arc<Mutex<Option<Waveform>>>,
+	scope: &'a Option<Arc<Mutex<ScopeBuffer>>>,
+	fade_ms: f64,
 }
 
-const VOLUME: f64 = 0.1;
-
-fn fill_buffer<'a, T, F: Fn(f64) -> T>(
-	static_data: StaticData<'a>,
-	buffer: &'a mut [T],
-	closure: F,
-) {
-	let StaticData {
-		counter,
-		sample_rate,
-		channel_count,
-		source,
-		terminating,
-	} = static_data;
+// how long a metronome click rings for before falling silent
+const CLICK_MS: f64 = 15.0;
+
+// a click on every beat, derived directly from the sample counter rather
+// than from `play_source`, so it stays in sync even while the song itself
+// is paused or fading. `beat_seconds` and `beats_per_measure` are captured
+// once at startup and don't follow `Tempo` instructions mid-song, the same
+// limitation swing's eighth-note grouping already has.
+struct Metronome {
+	beat_seconds: f64,
+	beats_per_measure: u32,
+}
 
-	assert!(buffer.len() % channel_count == 0);
+impl Metronome {
+	fn new(source: &Source) -> Self {
+		Metronome {
+			beat_seconds: source.beat_seconds(),
+			beats_per_measure: source.beats_per_measure(),
+		}
+	}
 
-	for i in 0 .. (buffer.len() / channel_count) {
-		let t = (*counter as f64) / (sample_rate as f64);
-		let val = match play_source(t, source) {
-			Some(signal) => signal * VOLUME,
-			None => {
-				*terminating = true;
-				0.0
-			},
-		};
+	// the click's signal at absolute time `t`, or 0.0 between clicks
+	fn tick(&self, t: f64) -> f64 {
+		let beat_index = (t / self.beat_seconds).floor() as i64;
+		let time_into_beat = t - beat_index as f64 * self.beat_seconds;
 
-		for j in 0 .. channel_count {
-			buffer[channel_count * i + j] = closure(val);
+		if time_into_beat * 1000.0 > CLICK_MS {
+			return 0.0;
 		}
 
-		*counter += 1;
+		let is_downbeat = beat_index.rem_euclid(i64::from(self.beats_per_measure)) == 0;
+		let frequency = if is_downbeat {1600.0} else {1000.0};
+		let envelope = 1.0 - time_into_beat * 1000.0 / CLICK_MS;
+
+		(2.0 * std::f64::consts::PI * frequency * time_into_beat).sin() * envelope
 	}
 }
 
-const A4: f64 = 440.0;
-const TAU: f64 = 2.0 * PI;
-const BPM: f64 = 120.0;
+// waveshaping overdrive on the master signal: scales by `drive` then
+// soft-clips with `tanh`, dividing back out by `tanh(drive)` so the clip
+// doesn't also change the signal's overall loudness, then blends `mix` of
+// that against the dry signal. `drive: 1.0, mix: 0.0` is transparent;
+// stateless, unlike `BitCrush`, so there's nothing to persist across
+// buffer callbacks
+struct Distortion {
+	drive: f64,
+	mix: f64,
+}
+
+impl Distortion {
+	fn apply(&self, signal: (f64, f64)) -> (f64, f64) {
+		let shape = |x: f64| {
+			let driven = (x * self.drive).tanh() / self.drive.tanh().max(1e-6);
+
+			x + (driven - x) * self.mix
+		};
 
-enum Instruction {
-	Note {pitch: i32, length: f64},
-	Rest {length: f64},
+		(shape(signal.0).max(-1.0).min(1.0), shape(signal.1).max(-1.0).min(1.0))
+	}
 }
 
-impl Instruction {
-	fn length(&self) -> f64 {
-		match self {
-			Instruction::Note {length, ..} => *length,
-			Instruction::Rest {length} => *length,
-		}
+// the feedback coefficient used when `--dc-blocker-r` isn't given. Close
+// enough to 1.0 that the filter's cutoff sits well below any audible bass,
+// while still settling out DC within a fraction of a second
+const DEFAULT_DC_BLOCKER_R: f64 = 0.995;
+
+// a one-pole DC-blocking high-pass filter on the master output:
+// `y[n] = x[n] - x[n-1] + r*y[n-1]`. Waveforms with asymmetric duty cycles
+// (the sawtooth's branch math, an off-center `Pulse`) can build up a DC
+// offset that wastes headroom and thumps the output on start/stop; this
+// keeps it centered on zero before the final integer conversion. State
+// persists across cpal buffer callbacks the same way `BitCrush`'s does
+struct DcBlocker {
+	r: f64,
+	previous_input: (f64, f64),
+	previous_output: (f64, f64),
+}
+
+impl DcBlocker {
+	fn apply(&mut self, signal: (f64, f64)) -> (f64, f64) {
+		let block = |x: f64, previous_x: f64, previous_y: f64| x - previous_x + self.r * previous_y;
+
+		let output = (
+			block(signal.0, self.previous_input.0, self.previous_output.0),
+			block(signal.1, self.previous_input.1, self.previous_output.1),
+		);
+
+		self.previous_input = signal;
+		self.previous_output = output;
+
+		output
 	}
 }
 
-struct Track {
-	instructions: Vec<Instruction>,
-	start_of_instruction: f64,
-	current_instruction: usize,
+// the delay applied when `--haas-ms` isn't given; 0.0 is transparent
+const DEFAULT_HAAS_MS: f64 = 0.0;
+
+// widens the stereo image by delaying the right channel `haas_ms`
+// milliseconds behind the left (the Haas effect): short enough to read as
+// "wider" rather than as a discrete echo, since the ear fuses delays under
+// ~30ms back into a single, directional sound. `haas_ms: 0.0` is
+// transparent. The delay line persists across cpal buffer callbacks the
+// same way `BitCrush`'s held sample does
+struct Haas {
+	buffer: Vec<f64>,
+	cursor: usize,
 }
 
-impl Track {
-	fn new(instructions: Vec<Instruction>) -> Self {
-		Track {
-			instructions,
-			start_of_instruction: 0.0,
-			current_instruction: 0,
+impl Haas {
+	fn new(haas_ms: f64, sample_rate: u32) -> Self {
+		let delay_samples = ((haas_ms / 1000.0) * f64::from(sample_rate)).round().max(0.0) as usize;
+
+		Haas {buffer: vec![0.0; delay_samples], cursor: 0}
+	}
+
+	fn apply(&mut self, signal: (f64, f64)) -> (f64, f64) {
+		if self.buffer.is_empty() {
+			return signal;
 		}
+
+		let (left, right) = signal;
+		let delayed_right = self.buffer[self.cursor];
+
+		self.buffer[self.cursor] = right;
+		self.cursor = (self.cursor + 1) % self.buffer.len();
+
+		(left, delayed_right)
 	}
 }
 
-struct Source {
-	tracks: Vec<Track>,
+// quantizes output to `2^bits` levels and holds each output value for
+// `downsample` frames to simulate a lower sample rate, for a lo-fi
+// aesthetic. `bits: 16, downsample: 1` is transparent. `held` and `counter`
+// persist across cpal buffer callbacks the same way `Fade` does
+struct BitCrush {
+	bits: u32,
+	downsample: u32,
+	held: (f64, f64),
+	counter: u32,
 }
 
-fn play_source(t: f64, source: &mut Source) -> Option<f64> {
-	let outputs = source.tracks.iter_mut().map(
-		|track| play_track(t, track)
-	);
+impl BitCrush {
+	fn apply(&mut self, signal: (f64, f64)) -> (f64, f64) {
+		if self.counter == 0 {
+			let levels = (1u64 << self.bits.min(31)) as f64;
+			let quantize = |x: f64| {
+				let normalized = (x.max(-1.0).min(1.0) * 0.5 + 0.5) * (levels - 1.0);
 
-	let mut final_output = None;
+				(normalized.round() / (levels - 1.0)) * 2.0 - 1.0
+			};
 
-	for output in outputs {
-		final_output = match (final_output, output) {
-			(Option::None, Option::None) => None,
-			(Option::None, x @ Option::Some(_)) => x,
-			(x @ Option::Some(_), None) => x,
-			(Option::Some(x), Option::Some(y)) => Some(x + y),
-		};
-	}
+			self.held = (quantize(signal.0), quantize(signal.1));
+		}
 
-	final_output
+		self.counter = (self.counter + 1) % self.downsample;
+
+		self.held
+	}
 }
 
-// this returns None to signal end of source
-fn play_track(t: f64, track: &mut Track) -> Option<f64> {
-	let instructions = &track.instructions;
-	let start_of_instruction = &mut track.start_of_instruction;
-	let current_instruction = &mut track.current_instruction;
+// the compressor threshold/ratio/attack/release used when their flags
+// aren't given. `ratio: 1.0` (no gain reduction at any level) makes the
+// whole effect transparent regardless of the other three
+const DEFAULT_COMPRESSOR_THRESHOLD: f64 = 1.0;
+const DEFAULT_COMPRESSOR_RATIO: f64 = 1.0;
+const DEFAULT_COMPRESSOR_ATTACK_MS: f64 = 5.0;
+const DEFAULT_COMPRESSOR_RELEASE_MS: f64 = 50.0;
+
+// a feed-forward compressor on the master output: an envelope follower
+// tracks the signal's peak, and whatever it reports above `threshold` (in
+// linear amplitude) is turned down by `ratio`, e.g. a 4.0 ratio means a
+// signal 4 dB over threshold comes out only 1 dB over. `attack_coeff`/
+// `release_coeff` are one-pole smoothing coefficients derived once from
+// `attack_ms`/`release_ms`, the same way `Haas::new` bakes `haas_ms` down
+// to a sample count; `envelope` persists across cpal buffer callbacks the
+// same way `Fade` does
+struct Compressor {
+	threshold: f64,
+	ratio: f64,
+	attack_coeff: f64,
+	release_coeff: f64,
+	envelope: f64,
+}
 
-	let measure_time = 60.0 / BPM * 4.0;
+impl Compressor {
+	fn new(threshold: f64, ratio: f64, attack_ms: f64, release_ms: f64, sample_rate: u32) -> Self {
+		let coeff = |time_ms: f64| (-1.0 / (time_ms / 1000.0 * f64::from(sample_rate)).max(1.0)).exp();
 
-	if *current_instruction >= instructions.len() {
-		return None;
+		Compressor {
+			threshold,
+			ratio: ratio.max(1.0),
+			attack_coeff: coeff(attack_ms),
+			release_coeff: coeff(release_ms),
+			envelope: 0.0,
+		}
 	}
 
-	let current_length = instructions[*current_instruction].length() * measure_time;
+	fn apply(&mut self, signal: (f64, f64)) -> (f64, f64) {
+		let peak = signal.0.abs().max(signal.1.abs());
+		let coeff = if peak > self.envelope {self.attack_coeff} else {self.release_coeff};
 
-	if t > *start_of_instruction + current_length {
-		*start_of_instruction += current_length;
-		*current_instruction += 1;
+		self.envelope = peak + coeff * (self.envelope - peak);
 
-		if *current_instruction >= instructions.len() {
-			return None;
+		if self.envelope <= self.threshold || self.ratio <= 1.0 {
+			return signal;
 		}
+
+		// do the gain-reduction math in dB, where "turn down by `ratio`" is
+		// just dividing the amount over threshold, then convert the result
+		// back to a linear gain to apply to the actual signal
+		let envelope_db = 20.0 * self.envelope.max(1e-12).log10();
+		let threshold_db = 20.0 * self.threshold.max(1e-12).log10();
+		let over_db = envelope_db - threshold_db;
+		let gain_db = (threshold_db + over_db / self.ratio) - envelope_db;
+		let gain = 10.0f64.powf(gain_db / 20.0);
+
+		(signal.0 * gain, signal.1 * gain)
 	}
+}
 
-	Some(match instructions[*current_instruction] {
-		Instruction::Note {pitch, length} => {
-			note_gen(t - *start_of_instruction, pitch, length * measure_time)
-		},
-		Instruction::Rest {..} => 0.0,
-	})
+// logs a warning if filling a buffer of `frame_count` frames took longer
+// than that buffer is worth in real time (its real-time budget at
+// `sample_rate`), meaning synthesis is falling behind and the output will
+// glitch. Cheap enough to call unconditionally every buffer callback
+fn warn_if_underrun(elapsed: Duration, frame_count: usize, sample_rate: u32) {
+	let budget = Duration::from_secs_f64(frame_count as f64 / f64::from(sample_rate));
+
+	if elapsed > budget {
+		eprintln!(
+			"warning: filling a {}-frame buffer took {:?}, exceeding its {:?} real-time budget",
+			frame_count,
+			elapsed,
+			budget,
+		);
+	}
 }
 
-fn note_gen(t: f64, pitch: i32, length: f64) -> f64 {
-	let generator = if cfg!(feature = "sin_wave") {
-		sin_wave
-	} else {
-		sawtooth
-	};
+// fills every sample of every channel in `buffer` with the same steady sine,
+// advancing `t` by `dt` once per frame (not per sample) so every channel of
+// a frame stays in phase; used by `--tone`, which skips `Source` and the
+// whole effects chain entirely
+fn fill_tone<T, F: Fn(f64) -> T>(
+	buffer: &mut [T],
+	channel_count: usize,
+	t: &mut f64,
+	dt: f64,
+	frequency: f64,
+	amplitude: f64,
+	closure: F,
+) {
+	assert!(buffer.len() % channel_count == 0);
 
-	generator(t * pitch_compute(pitch)) * envelope(t / length) * 0.96f64.powi(pitch)
-}
+	for frame in buffer.chunks_mut(channel_count) {
+		for slot in frame {
+			*slot = closure(sin_wave(*t * frequency) * amplitude);
+		}
 
-fn sin_wave(x: f64) -> f64 {
-	(x * TAU).sin()
+		*t += dt;
+	}
 }
 
-fn sawtooth(mut x: f64) -> f64 {
-	x %= 1.0;
+fn fill_buffer<'a, T, F: Fn(f64) -> T>(
+	static_data: StaticData<'a>,
+	buffer: &'a mut [T],
+	closure: F,
+) {
+	let StaticData {
+		counter,
+		sample_rate,
+		channel_count,
+		source,
+		terminating,
+		fade,
+		pause,
+		bit_crush,
+		distortion,
+		dc_blocker,
+		haas,
+		compressor,
+		prime_remaining,
+		metronome,
+		bend,
+		waveform_select,
+		scope,
+		fade_ms,
+	} = static_data;
+
+	assert!(buffer.len() % channel_count == 0);
+
+	// read once per buffer rather than once per sample; a pitch wheel move
+	// mid-buffer landing a few milliseconds late is inaudible
+	source.set_bend_semitones(*bend.lock().unwrap());
+
+	// `take` so an unchanged selection isn't re-queued on every buffer;
+	// `Source::set_waveform` itself defers the actual swap to each track's
+	// next note boundary
+	if let Some(waveform) = waveform_select.lock().unwrap().take() {
+		source.set_waveform(waveform);
+	}
+
+	let fade_total = ((sample_rate as f64) * fade_ms / 1000.0) as u32;
+	let fade_total = fade_total.max(1);
+	let pause_step = 1.0 / ((sample_rate as f64) * PAUSE_FADE_MS / 1000.0).max(1.0);
+
+	for i in 0 .. (buffer.len() / channel_count) {
+		let target_gain = if pause.flag.load(Ordering::Relaxed) {0.0} else {1.0};
+
+		// once the gain has fully ramped down to a pause, skip play_source
+		// and the sample counter entirely so the song resumes exactly where
+		// it left off instead of skipping ahead by however long it was paused
+		let (left, right) = if pause.gain <= 0.0 && target_gain <= 0.0 {
+			(0.0, 0.0)
+		} else {
+			let t = (*counter as f64) / (sample_rate as f64);
+
+			if fade.remaining.is_none() {
+				match play_source(t, source, sample_rate) {
+					Some(signal) => fade.last_sample = signal,
+					None => fade.remaining = Some(fade_total),
+				}
+			}
+
+			let (l, r) = match fade.remaining {
+				Some(remaining) => {
+					let fraction = remaining as f64 / fade_total as f64;
+					let (l, r) = fade.last_sample;
+
+					if remaining == 0 {
+						*terminating = true;
+					} else {
+						fade.remaining = Some(remaining - 1);
+					}
+
+					(l * fraction, r * fraction)
+				},
+				None => fade.last_sample,
+			};
+
+			let click = metronome.as_ref().map_or(0.0, |metronome| metronome.tick(t));
+			let (l, r) = (l + click, r + click);
 
-	if 0.0 <= x && x < 0.25 {
-		return x * 4.0;
+			if pause.gain < target_gain {
+				pause.gain = (pause.gain + pause_step).min(target_gain);
+			} else {
+				pause.gain = (pause.gain - pause_step).max(target_gain);
+			}
+
+			// ramps up from silence over the first `fade_total` samples so
+			// playback doesn't pop on hardware by jumping straight to full
+			// envelope; a no-op for the rest of the song once it reaches 1.0
+			let fade_in_gain = (*counter as f64 / fade_total as f64).min(1.0);
+
+			*counter += 1;
+
+			(l * pause.gain * fade_in_gain, r * pause.gain * fade_in_gain)
+		};
+
+		let (left, right) = distortion.apply((left, right));
+		let (left, right) = bit_crush.apply((left, right));
+		let (left, right) = dc_blocker.apply((left, right));
+		let (left, right) = haas.apply((left, right));
+		let (left, right) = compressor.apply((left, right));
+
+		// force silence outright for the first PRIME_MS, regardless of
+		// whatever synthesis/effects produced above; see PRIME_MS
+		let (left, right) = if *prime_remaining > 0 {
+			*prime_remaining -= 1;
+
+			(0.0, 0.0)
+		} else {
+			(left, right)
+		};
+
+		if let Some(scope) = scope {
+			scope.lock().unwrap().push((left + right) * 0.5);
+		}
+
+		match channel_count {
+			1 => {
+				let mono = (left + right) * 0.5;
+
+				buffer[i] = closure(soft_limit(mono));
+			},
+			2 => {
+				buffer[channel_count * i] = closure(soft_limit(left));
+				buffer[channel_count * i + 1] = closure(soft_limit(right));
+			},
+			// surround layouts (4.0, 5.1, 7.1, ...) all agree on putting the
+			// front left/right pair first, so the stereo mix lands there
+			// undistorted; center, LFE, and rear channels are left silent
+			// rather than duplicating the stereo mix into them, which would
+			// smear the panning the front pair already carries
+			_ => {
+				buffer[channel_count * i] = closure(soft_limit(left));
+				buffer[channel_count * i + 1] = closure(soft_limit(right));
+
+				for j in 2 .. channel_count {
+					buffer[channel_count * i + j] = closure(soft_limit(0.0));
+				}
+			},
+		}
 	}
-	
-	if 0.25 <= x && x < 0.75 {
-		return 2.0 - x * 4.0;
+}
+
+// how many recent mono samples the oscilloscope keeps around; wide enough
+// to cover a couple of waveform cycles at typical pitches without needing
+// to know the sample rate up front
+const SCOPE_BUFFER_LEN: usize = 4096;
+
+// a fixed-size ring buffer of recent mono output samples, written by
+// `fill_buffer` every sample and read by the `--scope` thread roughly 30
+// times a second. Bounded so memory use doesn't grow with playback length;
+// pushing past the end just overwrites the oldest sample
+struct ScopeBuffer {
+	samples: Vec<f64>,
+	cursor: usize,
+}
+
+impl ScopeBuffer {
+	fn new(len: usize) -> Self {
+		ScopeBuffer {samples: vec![0.0; len], cursor: 0}
 	}
 
-	if 0.75 <= x && x < 1.0 {
-		return x * 4.0 - 4.0;
+	fn push(&mut self, sample: f64) {
+		self.samples[self.cursor] = sample;
+		self.cursor = (self.cursor + 1) % self.samples.len();
 	}
 
-	panic!("invalid input")
+	// the buffer's contents in chronological order, oldest first
+	fn ordered(&self) -> Vec<f64> {
+		let (tail, head) = self.samples.split_at(self.cursor);
+
+		head.iter().chain(tail.iter()).copied().collect()
+	}
 }
 
-fn pitch_compute(pitch: i32) -> f64 {
-	A4 * 2.0f64.powf(1.0 / 12.0).powi(pitch)
+const SCOPE_FRAME_MS: u64 = 33;
+const SCOPE_WIDTH: usize = 80;
+const SCOPE_HEIGHT: usize = 20;
+
+// runs for as long as the process does, redrawing the oscilloscope from
+// `buffer` at roughly 30fps. A separate thread rather than piggybacking on
+// `fill_buffer` so a slow terminal never risks stealing time from audio
+// synthesis
+fn spawn_scope_thread(buffer: Arc<Mutex<ScopeBuffer>>) {
+	thread::spawn(move || {
+		loop {
+			let samples = buffer.lock().unwrap().ordered();
+
+			render_scope(&samples);
+
+			thread::sleep(Duration::from_millis(SCOPE_FRAME_MS));
+		}
+	});
 }
 
-fn envelope(x: f64) -> f64 {
-	if x < 0.0 || x > 1.0 {
-		return 0.0;
+// draws `samples` (oldest first) as a scrolling ASCII oscilloscope: each
+// terminal column samples one point spread evenly across the buffer, and
+// each row is a discretized amplitude band, zero at the vertical center
+fn render_scope(samples: &[f64]) {
+	if samples.is_empty() {
+		return;
 	}
 
-	if x < 0.1 {
-		return x * 10.0;
+	let mut rows = vec![vec![' '; SCOPE_WIDTH]; SCOPE_HEIGHT];
+
+	for column in 0 .. SCOPE_WIDTH {
+		let index = column * samples.len() / SCOPE_WIDTH;
+		let sample = samples[index].max(-1.0).min(1.0);
+		let row = ((1.0 - sample) * 0.5 * (SCOPE_HEIGHT - 1) as f64).round() as usize;
+
+		rows[row.min(SCOPE_HEIGHT - 1)][column] = '*';
 	}
 
-	if x > 0.9 {
-		return (1.0 - x) * 10.0;
+	// clear the screen and return the cursor home so each frame overwrites
+	// the last instead of scrolling the terminal
+	print!("\x1B[2J\x1B[H");
+
+	for row in rows {
+		let line: String = row.into_iter().collect();
+
+		println!("{}", line);
 	}
+}
+
+// the semitone range a maxed-out pitch wheel bends by, matching the MIDI
+// default (most controllers and DAWs assume +/-2 semitones unless a Registered
+// Parameter Number message says otherwise, which this basic listener doesn't
+// handle)
+const PITCH_BEND_RANGE_SEMITONES: f64 = 2.0;
+
+// connects to the first available MIDI input device and updates `bend` from
+// its pitch-bend messages for as long as the process runs. Note-on/off
+// messages are ignored for now; this is deliberately just the "bend the whole
+// output" slice of turning the synth into a playable instrument.
+fn spawn_pitch_bend_listener(bend: Arc<Mutex<f64>>) {
+	let mut midi_in = match midir::MidiInput::new("megalovania-pitch-bend") {
+		Ok(midi_in) => midi_in,
+		Err(error) => {
+			eprintln!("warning: --midi-input failed to initialize: {}", error);
+
+			return;
+		},
+	};
+
+	midi_in.ignore(midir::Ignore::All);
+
+	let ports = midi_in.ports();
+	let port = match ports.first() {
+		Some(port) => port,
+		None => {
+			eprintln!("warning: --midi-input requested but no MIDI input device is connected");
+
+			return;
+		},
+	};
+
+	let port_name = midi_in.port_name(port).unwrap_or_else(|_| "unknown device".to_string());
 
-	return 1.0;
+	let connection = midi_in.connect(port, "megalovania-pitch-bend", move |_timestamp, message, _| {
+		// pitch bend is status byte 0xE0..=0xEF followed by two 7-bit data
+		// bytes forming a 14-bit value, centered at 8192
+		if message.len() == 3 && message[0] & 0xf0 == 0xe0 {
+			let value = (u16::from(message[2]) << 7) | u16::from(message[1]);
+			let normalized = (f64::from(value) - 8192.0) / 8192.0;
+
+			*bend.lock().unwrap() = normalized * PITCH_BEND_RANGE_SEMITONES;
+		}
+	}, ());
+
+	match connection {
+		// the connection has to outlive this function for its callback to
+		// keep firing; there's no natural point during playback to close it
+		Ok(connection) => {
+			println!("--midi-input: listening for pitch bend on {}", port_name);
+
+			std::mem::forget(connection);
+		},
+		Err(error) => eprintln!("warning: --midi-input failed to connect to {}: {}", port_name, error),
+	}
 }
 
-const WHOLE: f64 = 1.0;
-const HALF: f64 = 1.0 / 2.0;
-const QUARTER: f64 = 1.0 / 4.0;
-const N8TH: f64 = 1.0 / 8.0;
-const N16TH: f64 = 1.0 / 16.0;
+// soft-saturates a signal that's expected to sit around -1.0..=1.0, gently
+// rounding off peaks from stacked tracks/chords instead of hard-clipping.
+// tanh is already ~identity near 0 and guarantees the result stays in range,
+// so the int conversion closures never see an out-of-range `val`.
+fn soft_limit(x: f64) -> f64 {
+	x.tanh()
+}
 
 fn treble() -> Track {
 	use Instruction::{Note, Rest};
 
 	Track::new(vec![
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
-
-		Note {pitch: -21, length: N16TH},
-		Note {pitch: -21, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+
+		Note {pitch: -21, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -21, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
-
-		Note {pitch: -22, length: N16TH},
-		Note {pitch: -22, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+
+		Note {pitch: -22, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -22, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
-
-		Note {pitch: -23, length: N16TH},
-		Note {pitch: -23, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+
+		Note {pitch: -23, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -23, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
-
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
-
-		Note {pitch: -21, length: N16TH},
-		Note {pitch: -21, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+
+		Note {pitch: -21, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -21, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
-
-		Note {pitch: -22, length: N16TH},
-		Note {pitch: -22, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+
+		Note {pitch: -22, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -22, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
-
-		Note {pitch: -23, length: N16TH},
-		Note {pitch: -23, length: N16TH},
-		Note {pitch: -7, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+
+		Note {pitch: -23, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -23, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -7, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -12, length: N8TH},
+		Note {pitch: -12, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 		Rest {length: N16TH},
-		Note {pitch: -13, length: N8TH},
-		Note {pitch: -14, length: N8TH},
-		Note {pitch: -16, length: N8TH},
-		Note {pitch: -19, length: N16TH},
-		Note {pitch: -16, length: N16TH},
-		Note {pitch: -14, length: N16TH},
+		Note {pitch: -13, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -19, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -16, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Note {pitch: -14, length: N16TH, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
 
 		Rest {length: N16TH},
-	])
+	], Waveform::SawtoothAntiAliased, Adsr::default(), -0.3)
+		.with_portamento(40.0)
 }
 
 fn bass() -> Track {
-	use Instruction::{Note, Rest};
+	use Instruction::{Note, Rest, Slide};
 
 	Track::new(vec![
 		Rest {length: WHOLE},
@@ -403,17 +1617,77 @@ fn bass() -> Track {
 
 		Rest {length: WHOLE},
 
-		Note {pitch: -31, length: WHOLE},
+		Note {pitch: -31, length: WHOLE, velocity: 0.7, tied: false, gate: 1.0, probability: 1.0, pan: None},
 
-		Note {pitch: -33, length: WHOLE},
+		Note {pitch: -33, length: WHOLE, velocity: 0.8, tied: false, gate: 1.0, probability: 1.0, pan: None},
 
-		Note {pitch: -34, length: WHOLE},
+		Note {pitch: -34, length: WHOLE, velocity: 0.9, tied: false, gate: 1.0, probability: 1.0, pan: None},
 
-		Note {pitch: -35, length: 1.5 * QUARTER},
-		Note {pitch: -35, length: N8TH},
-		Note {pitch: -33, length: HALF},
-		Note {pitch: -33, length: N8TH},
+		Note {pitch: -35, length: dotted(QUARTER), velocity: FULL_VELOCITY, tied: false, gate: 0.4, probability: 1.0, pan: None},
+		Note {pitch: -35, length: N8TH, velocity: FULL_VELOCITY, tied: false, gate: 0.4, probability: 1.0, pan: None},
+		Note {pitch: -33, length: HALF, velocity: FULL_VELOCITY, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		Slide {from: -45, to: -33, length: N8TH},
 
 		Rest {length: N16TH},
-	])
+	], Waveform::Square, Adsr {attack: 0.02, decay: 0.1, sustain: 0.6, release: 0.05, curve: 1.0}, 0.3)
+		.with_vibrato(Vibrato {rate_hz: 5.5, depth_semitones: 0.15, delay: 0.3})
+		.with_tremolo(Tremolo {rate_hz: 6.0, depth: 0.3})
+		.with_gain(0.85)
+		.with_sub_level(0.4)
+}
+
+// a simple hi-hat pattern: `Waveform::Noise` ignores `pitch`, so it's left at
+// 0. The very short gate and fast decay turn the noise into a tick instead
+// of a wash.
+fn hihat() -> Track {
+	use Instruction::{Note, Rest};
+
+	Track::new(vec![
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.7, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.7, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+
+		Rest {length: WHOLE},
+
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.7, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.7, tied: false, gate: 0.3, probability: 1.0, pan: None},
+		Note {pitch: 0, length: N8TH, velocity: 0.5, tied: false, gate: 0.3, probability: 1.0, pan: None},
+	], Waveform::Noise, Adsr {attack: 0.001, decay: 0.03, sustain: 0.0, release: 0.01, curve: 1.0}, 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// a constant (DC) input should decay toward zero rather than pass
+	// straight through, since that's the whole point of the filter
+	#[test]
+	fn dc_blocker_decays_a_constant_input_to_zero() {
+		let mut dc_blocker = DcBlocker {
+			r: DEFAULT_DC_BLOCKER_R,
+			previous_input: (0.0, 0.0),
+			previous_output: (0.0, 0.0),
+		};
+
+		let mut output = (1.0, 1.0);
+
+		for _ in 0 .. 10_000 {
+			output = dc_blocker.apply((1.0, 1.0));
+		}
+
+		assert!(output.0.abs() < 1e-6, "left channel should have decayed to ~0, got {}", output.0);
+		assert!(output.1.abs() < 1e-6, "right channel should have decayed to ~0, got {}", output.1);
+	}
 }
@@ -0,0 +1,478 @@
+// a minimal Standard MIDI File (SMF) reader, just enough to turn a `.mid`
+// file into a `Source`. No external MIDI crate is used, matching how the
+// rest of this crate hand-rolls its file formats (see `write_wav`).
+
+use crate::{Adsr, Instruction, Source, Track, Waveform, FULL_VELOCITY, GRACE_LENGTH};
+
+#[derive(Debug)]
+pub enum MidiError {
+	Io(std::io::Error),
+	Format(String),
+}
+
+impl std::fmt::Display for MidiError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			MidiError::Io(error) => write!(f, "i/o error reading midi file: {}", error),
+			MidiError::Format(message) => write!(f, "malformed midi file: {}", message),
+		}
+	}
+}
+
+impl std::error::Error for MidiError {}
+
+impl From<std::io::Error> for MidiError {
+	fn from(error: std::io::Error) -> Self {
+		MidiError::Io(error)
+	}
+}
+
+// default 120bpm, used when a file has no Set Tempo meta event
+const DEFAULT_BPM: f64 = 120.0;
+
+// reads a standard MIDI file and maps each track chunk carrying notes to a
+// `Track`; channels within a chunk are not separated. Time signature is
+// assumed to be 4/4, and only the first Set Tempo meta event found in the
+// file is honored (later tempo changes are ignored). Overlapping notes in a
+// chunk are flattened: a note-on, on any channel, while another note is
+// still sounding in that chunk ends the earlier note early, rather than
+// producing a chord.
+pub fn from_midi(path: &str) -> Result<Source, MidiError> {
+	let bytes = std::fs::read(path)?;
+
+	let mut pos = 0;
+
+	let (_format, track_count, division) = read_header(&bytes, &mut pos)?;
+
+	if division & 0x8000 != 0 {
+		return Err(MidiError::Format("SMPTE timecode division is not supported".to_string()));
+	}
+
+	let mut bpm = None;
+	let mut tracks = Vec::new();
+
+	for _ in 0 .. track_count {
+		let chunk = read_chunk(&bytes, &mut pos)?;
+
+		if chunk.id != *b"MTrk" {
+			continue;
+		}
+
+		let parsed = parse_track_chunk(chunk.data, division, &mut bpm);
+
+		tracks.push(parsed);
+	}
+
+	let tracks: Vec<Track> = tracks.into_iter()
+		.filter(|instructions: &Vec<Instruction>| !instructions.is_empty())
+		.map(|instructions| Track::new(instructions, Waveform::Sawtooth, Adsr::default(), 0.0))
+		.collect();
+
+	Ok(Source::new(tracks, bpm.unwrap_or(DEFAULT_BPM), Some(0)))
+}
+
+struct Chunk<'a> {
+	id: [u8; 4],
+	data: &'a [u8],
+}
+
+fn read_header(bytes: &[u8], pos: &mut usize) -> Result<(u16, u16, u16), MidiError> {
+	let chunk = read_chunk(bytes, pos)?;
+
+	if chunk.id != *b"MThd" || chunk.data.len() < 6 {
+		return Err(MidiError::Format("missing MThd header chunk".to_string()));
+	}
+
+	let format = read_u16(chunk.data, 0);
+	let track_count = read_u16(chunk.data, 2);
+	let division = read_u16(chunk.data, 4);
+
+	Ok((format, track_count, division))
+}
+
+fn read_chunk<'a>(bytes: &'a [u8], pos: &mut usize) -> Result<Chunk<'a>, MidiError> {
+	if *pos + 8 > bytes.len() {
+		return Err(MidiError::Format("unexpected end of file while reading a chunk header".to_string()));
+	}
+
+	let mut id = [0u8; 4];
+	id.copy_from_slice(&bytes[*pos .. *pos + 4]);
+
+	let length = read_u32(bytes, *pos + 4) as usize;
+	let data_start = *pos + 8;
+	let data_end = data_start + length;
+
+	if data_end > bytes.len() {
+		return Err(MidiError::Format("chunk length runs past the end of the file".to_string()));
+	}
+
+	*pos = data_end;
+
+	Ok(Chunk {id, data: &bytes[data_start .. data_end]})
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+	u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+	u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+// reads a MIDI variable-length quantity, returning the value and the new position
+fn read_varlen(bytes: &[u8], pos: &mut usize) -> u32 {
+	let mut value: u32 = 0;
+
+	loop {
+		let byte = bytes[*pos];
+		*pos += 1;
+
+		value = (value << 7) | u32::from(byte & 0x7f);
+
+		if byte & 0x80 == 0 {
+			break;
+		}
+	}
+
+	value
+}
+
+// a note currently sounding on a channel, waiting for its matching note-off
+struct HeldNote {
+	pitch: i32,
+	start: f64,
+	velocity: f64,
+}
+
+fn parse_track_chunk(data: &[u8], division: u16, bpm: &mut Option<f64>) -> Vec<Instruction> {
+	let mut instructions = Vec::new();
+	let mut pos = 0;
+	let mut running_status: Option<u8> = None;
+
+	let mut absolute_ticks: u64 = 0;
+	let mut cursor = 0.0;
+	let mut last_end = 0.0;
+	let mut held: Option<HeldNote> = None;
+
+	while pos < data.len() {
+		absolute_ticks += u64::from(read_varlen(data, &mut pos));
+		cursor = ticks_to_length(absolute_ticks, division);
+
+		let mut status = data[pos];
+
+		if status & 0x80 != 0 {
+			pos += 1;
+			running_status = Some(status);
+		} else {
+			status = running_status.unwrap_or(0);
+		}
+
+		match status {
+			0xff => {
+				let meta_type = data[pos];
+				pos += 1;
+
+				let length = read_varlen(data, &mut pos) as usize;
+				let meta_data = &data[pos .. pos + length];
+				pos += length;
+
+				if meta_type == 0x51 && meta_data.len() == 3 && bpm.is_none() {
+					let microseconds_per_quarter = u32::from(meta_data[0]) << 16
+						| u32::from(meta_data[1]) << 8
+						| u32::from(meta_data[2]);
+
+					*bpm = Some(60_000_000.0 / f64::from(microseconds_per_quarter));
+				}
+			},
+			0xf0 | 0xf7 => {
+				let length = read_varlen(data, &mut pos) as usize;
+				pos += length;
+			},
+			_ => {
+				let kind = status & 0xf0;
+				let data_byte_count = channel_message_size(kind);
+				let message = &data[pos .. pos + data_byte_count];
+				pos += data_byte_count;
+
+				if kind == 0x90 && message[1] > 0 {
+					if let Some(note) = held.take() {
+						instructions.push(close_note(note, cursor));
+						last_end = cursor;
+					}
+
+					if cursor > last_end {
+						instructions.push(Instruction::Rest {length: cursor - last_end});
+					}
+
+					held = Some(HeldNote {
+						pitch: i32::from(message[0]) - 69,
+						start: cursor,
+						velocity: f64::from(message[1]) / 127.0,
+					});
+				} else if kind == 0x80 || (kind == 0x90 && message[1] == 0) {
+					if let Some(note) = held.take() {
+						if (i32::from(message[0]) - 69) == note.pitch {
+							instructions.push(close_note(note, cursor));
+							last_end = cursor;
+						} else {
+							held = Some(note);
+						}
+					}
+				}
+			},
+		}
+	}
+
+	if let Some(note) = held.take() {
+		instructions.push(close_note(note, cursor));
+	}
+
+	instructions
+}
+
+fn close_note(note: HeldNote, end: f64) -> Instruction {
+	Instruction::Note {
+		pitch: note.pitch,
+		length: (end - note.start).max(0.0),
+		velocity: note.velocity,
+		tied: false,
+		gate: 1.0,
+		probability: 1.0,
+		pan: None,
+	}
+}
+
+// number of data bytes following a channel voice status byte's high nibble
+fn channel_message_size(kind: u8) -> usize {
+	match kind {
+		0xc0 | 0xd0 => 1,
+		_ => 2,
+	}
+}
+
+// converts an absolute tick position into our length unit, where 1.0 is a
+// whole note (four quarter notes) regardless of tempo
+fn ticks_to_length(ticks: u64, division: u16) -> f64 {
+	let quarter_notes = ticks as f64 / f64::from(division);
+
+	quarter_notes / 4.0
+}
+
+// the inverse: how many ticks a length in our unit occupies, rounded to
+// the nearest tick. Rounding is the main source of drift on a long track,
+// though it's small enough at this resolution to be inaudible
+fn length_to_ticks(length: f64, division: u16) -> u32 {
+	(length * 4.0 * f64::from(division)).round() as u32
+}
+
+// ticks per quarter note used when writing files; fine-grained enough that
+// our fractional note lengths round to it without noticeable drift
+const EXPORT_DIVISION: u16 = 480;
+
+// the inverse of `from_midi`: writes a standard MIDI file with one track
+// per `Track`, converting each `Instruction::Note` to a note-on/note-off
+// pair (the note-off falls at `length * gate`, matching the gap our own
+// playback leaves before the next instruction) and each `Instruction::Rest`
+// to a gap between events. `Instruction::Chord` becomes simultaneous notes
+// on the same channel; `Instruction::Slide` has no MIDI equivalent and is
+// exported as a single held note at its `from` pitch. Only the source's
+// starting tempo is written, as a Set Tempo meta event at the very start of
+// the first track; later `Instruction::Tempo` changes are written in place
+// on whichever track contains them.
+pub fn export_midi(source: &Source, path: &str) -> Result<(), MidiError> {
+	let mut bytes = Vec::new();
+
+	bytes.extend_from_slice(b"MThd");
+	bytes.extend_from_slice(&write_u32(6));
+	bytes.extend_from_slice(&write_u16(1));
+	bytes.extend_from_slice(&write_u16(source.tracks.len() as u16));
+	bytes.extend_from_slice(&write_u16(EXPORT_DIVISION));
+
+	for (i, track) in source.tracks.iter().enumerate() {
+		let initial_bpm = if i == 0 {Some(source.bpm)} else {None};
+		let track_bytes = write_track_chunk(&track.instructions, initial_bpm);
+
+		bytes.extend_from_slice(b"MTrk");
+		bytes.extend_from_slice(&write_u32(track_bytes.len() as u32));
+		bytes.extend_from_slice(&track_bytes);
+	}
+
+	std::fs::write(path, bytes)?;
+
+	Ok(())
+}
+
+fn write_track_chunk(instructions: &[Instruction], initial_bpm: Option<f64>) -> Vec<u8> {
+	let mut events: Vec<(u32, Vec<u8>)> = Vec::new();
+
+	if let Some(bpm) = initial_bpm {
+		events.push((0, set_tempo_event(bpm)));
+	}
+
+	let mut cursor: u32 = 0;
+
+	for instruction in instructions {
+		match instruction {
+			Instruction::Note {pitch, length, velocity, gate, ..} => {
+				let length_ticks = length_to_ticks(*length, EXPORT_DIVISION);
+				let gated_ticks = length_to_ticks(*length * gate, EXPORT_DIVISION);
+
+				events.push((cursor, note_event(0x90, *pitch, *velocity)));
+				events.push((cursor + gated_ticks, note_event(0x80, *pitch, *velocity)));
+
+				cursor += length_ticks;
+			},
+			Instruction::Chord {pitches, length} => {
+				let length_ticks = length_to_ticks(*length, EXPORT_DIVISION);
+
+				for &pitch in pitches {
+					events.push((cursor, note_event(0x90, pitch, FULL_VELOCITY)));
+					events.push((cursor + length_ticks, note_event(0x80, pitch, FULL_VELOCITY)));
+				}
+
+				cursor += length_ticks;
+			},
+			Instruction::Slide {from, length, ..} => {
+				let length_ticks = length_to_ticks(*length, EXPORT_DIVISION);
+
+				events.push((cursor, note_event(0x90, *from, FULL_VELOCITY)));
+				events.push((cursor + length_ticks, note_event(0x80, *from, FULL_VELOCITY)));
+
+				cursor += length_ticks;
+			},
+			Instruction::Rest {length} => {
+				cursor += length_to_ticks(*length, EXPORT_DIVISION);
+			},
+			Instruction::Tempo {bpm} => {
+				events.push((cursor, set_tempo_event(*bpm)));
+			},
+			// no dedicated MIDI ornament event exists, so a grace note is
+			// exported as a very short note at its own fixed length; the
+			// note it "borrows" from was never actually shortened in the
+			// data, so nothing needs adjusting on the export side
+			Instruction::Grace {pitch} => {
+				let length_ticks = length_to_ticks(GRACE_LENGTH, EXPORT_DIVISION);
+
+				events.push((cursor, note_event(0x90, *pitch, FULL_VELOCITY)));
+				events.push((cursor + length_ticks, note_event(0x80, *pitch, FULL_VELOCITY)));
+
+				cursor += length_ticks;
+			},
+			// no dedicated MIDI dynamics event is written here (a real
+			// CC7/volume ramp would need many more events than this exporter
+			// otherwise generates); takes no time, like `Tempo`, but unlike
+			// `Tempo` there's no meta event to emit in its place
+			Instruction::Crescendo {..} => {},
+		}
+	}
+
+	// a stable sort keeps same-tick events (e.g. a chord's simultaneous
+	// note-ons) in the order they were pushed
+	events.sort_by_key(|(tick, _)| *tick);
+
+	let mut chunk = Vec::new();
+	let mut previous_tick = 0;
+
+	for (tick, event) in events {
+		chunk.extend_from_slice(&write_varlen(tick - previous_tick));
+		chunk.extend_from_slice(&event);
+
+		previous_tick = tick;
+	}
+
+	chunk.extend_from_slice(&write_varlen(0));
+	chunk.extend_from_slice(&[0xff, 0x2f, 0x00]); // end of track
+
+	chunk
+}
+
+// pitch offset 69 = A4, the same convention `from_midi` reads back with;
+// out-of-range results are clamped to the nearest valid MIDI note number
+fn note_event(status: u8, pitch: i32, velocity: f64) -> Vec<u8> {
+	let note_number = (pitch + 69).max(0).min(127) as u8;
+	let velocity_byte = ((velocity.max(0.0).min(1.0) * 127.0).round() as u8).max(1);
+
+	vec![status, note_number, velocity_byte]
+}
+
+fn set_tempo_event(bpm: f64) -> Vec<u8> {
+	let microseconds_per_quarter = (60_000_000.0 / bpm).round() as u32;
+	let bytes = microseconds_per_quarter.to_be_bytes();
+
+	vec![0xff, 0x51, 0x03, bytes[1], bytes[2], bytes[3]]
+}
+
+fn write_u16(value: u16) -> [u8; 2] {
+	value.to_be_bytes()
+}
+
+fn write_u32(value: u32) -> [u8; 4] {
+	value.to_be_bytes()
+}
+
+// the inverse of `read_varlen`
+fn write_varlen(value: u32) -> Vec<u8> {
+	let mut bytes = vec![(value & 0x7f) as u8];
+	let mut value = value >> 7;
+
+	while value > 0 {
+		bytes.push((value & 0x7f) as u8 | 0x80);
+		value >>= 7;
+	}
+
+	bytes.reverse();
+
+	bytes
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// round-trips a `Source` through `export_midi`/`from_midi` and checks
+	// the notes come back with the same pitches and lengths, since that's
+	// the property both functions are meant to preserve for each other
+	#[test]
+	fn from_midi_round_trips_a_simple_track() {
+		let instructions = vec![
+			Instruction::Note {pitch: 0, length: 0.25, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None},
+			Instruction::Note {pitch: 3, length: 0.25, velocity: 1.0, tied: false, gate: 1.0, probability: 1.0, pan: None},
+		];
+
+		let track = Track::new(instructions, Waveform::Sawtooth, Adsr::default(), 0.0);
+		let source = Source::new(vec![track], 120.0, Some(0));
+
+		let path = std::env::temp_dir().join("megalovania_from_midi_round_trip_test.mid");
+		let path = path.to_str().unwrap();
+
+		export_midi(&source, path).unwrap();
+		let read_back = from_midi(path).unwrap();
+
+		std::fs::remove_file(path).unwrap();
+
+		assert_eq!(read_back.tracks.len(), 1);
+
+		let notes: Vec<(i32, f64)> = read_back.tracks[0].instructions.iter().filter_map(|instruction| match instruction {
+			Instruction::Note {pitch, length, ..} => Some((*pitch, *length)),
+			_ => None,
+		}).collect();
+
+		assert_eq!(notes, vec![(0, 0.25), (3, 0.25)]);
+	}
+
+	// a file that isn't a MIDI file at all (missing the MThd header) should
+	// be reported as a format error, not panic
+	#[test]
+	fn from_midi_rejects_a_file_without_a_header() {
+		let path = std::env::temp_dir().join("megalovania_from_midi_bad_header_test.mid");
+		let path = path.to_str().unwrap();
+
+		std::fs::write(path, b"not a midi file").unwrap();
+
+		let result = from_midi(path);
+
+		std::fs::remove_file(path).unwrap();
+
+		assert!(matches!(result, Err(MidiError::Format(_))));
+	}
+}
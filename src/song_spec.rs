@@ -0,0 +1,212 @@
+// deserializes a `Source` from structured JSON or TOML, for songs generated
+// by a script rather than typed by hand in the text notation. Only gated in
+// via the `serde-support` feature, since neither `serde` nor a format crate
+// are needed otherwise.
+//
+// `Track`/`Source`/`DrumTrack` themselves aren't `Deserialize`: their fields
+// mix real musical settings with playback-internal bookkeeping (instruction
+// cursors, glide/loop state) that a song file has no business specifying.
+// Instead these `*Spec` structs cover only the settings a caller can build
+// with today (`Track::new` plus its most common builders) and convert into
+// the real types via `From`. Anything not listed here just keeps the
+// engine's usual default and can still be set in code after loading.
+
+use serde::Deserialize;
+
+use crate::{
+	Adsr, DrumInstruction, DrumTrack, Instruction, MixMode, PanLaw, Source, Temperament, Track,
+	TimeSignature, Tremolo, Unison, Vibrato, Waveform,
+};
+
+fn default_gain() -> f64 { 1.0 }
+fn default_volume() -> f64 { 0.1 }
+fn default_tuning() -> f64 { 440.0 }
+
+#[derive(Deserialize)]
+pub struct TrackSpec {
+	pub instructions: Vec<Instruction>,
+	pub waveform: Waveform,
+	#[serde(default)]
+	pub adsr: Adsr,
+	#[serde(default)]
+	pub pan: f64,
+	#[serde(default)]
+	pub vibrato: Option<Vibrato>,
+	#[serde(default)]
+	pub tremolo: Option<Tremolo>,
+	#[serde(default)]
+	pub portamento_ms: f64,
+	#[serde(default = "default_gain")]
+	pub gain: f64,
+	#[serde(default)]
+	pub sub_level: f64,
+	#[serde(default)]
+	pub unison: Option<Unison>,
+	#[serde(default)]
+	pub muted: bool,
+	#[serde(default)]
+	pub soloed: bool,
+}
+
+impl From<TrackSpec> for Track {
+	fn from(spec: TrackSpec) -> Self {
+		Track::new(spec.instructions, spec.waveform, spec.adsr, spec.pan)
+			.with_portamento(spec.portamento_ms)
+			.with_gain(spec.gain)
+			.with_sub_level(spec.sub_level)
+			.with_muted(spec.muted)
+			.with_soloed(spec.soloed)
+			.apply_optional(spec.vibrato, spec.tremolo, spec.unison)
+	}
+}
+
+// small local helper so `From<TrackSpec>` above can keep chaining through the
+// three builders that only apply when their spec field was actually present,
+// without breaking out of the builder-call style
+trait ApplyOptional {
+	fn apply_optional(self, vibrato: Option<Vibrato>, tremolo: Option<Tremolo>, unison: Option<Unison>) -> Self;
+}
+
+impl ApplyOptional for Track {
+	fn apply_optional(mut self, vibrato: Option<Vibrato>, tremolo: Option<Tremolo>, unison: Option<Unison>) -> Self {
+		if let Some(vibrato) = vibrato {
+			self = self.with_vibrato(vibrato);
+		}
+		if let Some(tremolo) = tremolo {
+			self = self.with_tremolo(tremolo);
+		}
+		if let Some(unison) = unison {
+			self = self.with_unison(unison);
+		}
+
+		self
+	}
+}
+
+#[derive(Deserialize)]
+pub struct DrumTrackSpec {
+	pub instructions: Vec<DrumInstruction>,
+	#[serde(default)]
+	pub pan: f64,
+	#[serde(default)]
+	pub muted: bool,
+	#[serde(default)]
+	pub soloed: bool,
+}
+
+impl From<DrumTrackSpec> for DrumTrack {
+	fn from(spec: DrumTrackSpec) -> Self {
+		DrumTrack::new(spec.instructions, spec.pan)
+			.with_muted(spec.muted)
+			.with_soloed(spec.soloed)
+	}
+}
+
+#[derive(Deserialize)]
+pub struct SourceSpec {
+	pub tracks: Vec<TrackSpec>,
+	#[serde(default)]
+	pub drum_tracks: Vec<DrumTrackSpec>,
+	pub bpm: f64,
+	#[serde(default)]
+	pub loop_count: Option<u32>,
+	#[serde(default = "default_volume")]
+	pub volume: f64,
+	#[serde(default)]
+	pub swing: f64,
+	#[serde(default = "default_tuning")]
+	pub tuning: f64,
+	#[serde(default)]
+	pub temperament: Option<Temperament>,
+	#[serde(default)]
+	pub crossfade_ms: f64,
+	#[serde(default)]
+	pub time_signature: TimeSignature,
+	#[serde(default)]
+	pub mix_mode: Option<MixMode>,
+	#[serde(default)]
+	pub pan_law: Option<PanLaw>,
+	#[serde(default)]
+	pub humanize: f64,
+}
+
+impl From<SourceSpec> for Source {
+	fn from(spec: SourceSpec) -> Self {
+		let tracks = spec.tracks.into_iter().map(Track::from).collect();
+		let drum_tracks = spec.drum_tracks.into_iter().map(DrumTrack::from).collect();
+
+		let mut source = Source::new(tracks, spec.bpm, spec.loop_count)
+			.with_drum_tracks(drum_tracks)
+			.with_volume(spec.volume)
+			.with_swing(spec.swing)
+			.with_tuning(spec.tuning)
+			.with_crossfade(spec.crossfade_ms)
+			.with_time_signature(spec.time_signature)
+			.with_humanize(spec.humanize);
+
+		if let Some(temperament) = spec.temperament {
+			source = source.with_temperament(temperament);
+		}
+		if let Some(mix_mode) = spec.mix_mode {
+			source = source.with_mix_mode(mix_mode);
+		}
+		if let Some(pan_law) = spec.pan_law {
+			source = source.with_pan_law(pan_law);
+		}
+
+		source
+	}
+}
+
+#[derive(Debug)]
+pub enum SongError {
+	Io(std::io::Error),
+	Json(serde_json::Error),
+	Toml(toml::de::Error),
+}
+
+impl std::fmt::Display for SongError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			SongError::Io(error) => write!(f, "i/o error reading song file: {}", error),
+			SongError::Json(error) => write!(f, "malformed song json: {}", error),
+			SongError::Toml(error) => write!(f, "malformed song toml: {}", error),
+		}
+	}
+}
+
+impl std::error::Error for SongError {}
+
+impl From<std::io::Error> for SongError {
+	fn from(error: std::io::Error) -> Self {
+		SongError::Io(error)
+	}
+}
+
+impl From<serde_json::Error> for SongError {
+	fn from(error: serde_json::Error) -> Self {
+		SongError::Json(error)
+	}
+}
+
+impl From<toml::de::Error> for SongError {
+	fn from(error: toml::de::Error) -> Self {
+		SongError::Toml(error)
+	}
+}
+
+// reads a `Source` from a JSON file shaped like `SourceSpec`
+pub fn from_json(path: &str) -> Result<Source, SongError> {
+	let contents = std::fs::read_to_string(path)?;
+	let spec: SourceSpec = serde_json::from_str(&contents)?;
+
+	Ok(spec.into())
+}
+
+// reads a `Source` from a TOML file shaped like `SourceSpec`
+pub fn from_toml(path: &str) -> Result<Source, SongError> {
+	let contents = std::fs::read_to_string(path)?;
+	let spec: SourceSpec = toml::from_str(&contents)?;
+
+	Ok(spec.into())
+}